@@ -0,0 +1,391 @@
+use std::{collections::BTreeMap, time::SystemTime};
+
+use bonsaidb::core::connection::AsyncConnection;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use serde::Serialize;
+use transmog_json::serde_json;
+
+use crate::{
+    api::{self, EventSource},
+    html,
+    schema::{
+        Event, GitHubActivityCounts, GitHubContributorCounts, GitHubDailyActivityCounts,
+        IssuesPayload, PullRequestPayload, Release, ReleasePayload,
+    },
+};
+
+/// The number of releases shown on a project's page.
+const RECENT_RELEASES: usize = 10;
+
+/// The number of issue and pull request events shown on a project's page.
+const RECENT_ISSUES: usize = 20;
+
+/// The number of contributors shown on a project's page.
+const TOP_CONTRIBUTORS: usize = 20;
+
+/// Returns the number of events on each day from `from` through `to`
+/// (inclusive). If `repository` is provided, only events from that repository
+/// (`owner/name`) are counted.
+pub async fn daily_totals<C: AsyncConnection>(
+    database: &C,
+    repository: Option<&str>,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> anyhow::Result<BTreeMap<NaiveDate, u64>> {
+    let start = api::start_of_day(from);
+    let end = api::end_of_day(to);
+    let mut totals = BTreeMap::new();
+    if let Some(repository) = repository {
+        let counts = database
+            .view::<GitHubActivityCounts>()
+            .with_key_range(
+                (repository.to_string(), start, String::new())
+                    ..(repository.to_string(), end, String::new()),
+            )
+            .reduce_grouped()
+            .await?;
+        for count in counts {
+            let (_, day, _) = count.key;
+            *totals
+                .entry(DateTime::<Utc>::from(day).date().naive_utc())
+                .or_default() += count.value;
+        }
+    } else {
+        let counts = database
+            .view::<GitHubDailyActivityCounts>()
+            .with_key_range(start..end)
+            .reduce_grouped()
+            .await?;
+        for count in counts {
+            totals.insert(
+                DateTime::<Utc>::from(count.key).date().naive_utc(),
+                count.value,
+            );
+        }
+    }
+    Ok(totals)
+}
+
+#[derive(Serialize, Debug)]
+pub struct Heatmap {
+    pub total: u64,
+    /// Each week starts on Sunday and contains seven days.
+    pub weeks: Vec<Vec<HeatmapDay>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct HeatmapDay {
+    pub iso_date: String,
+    pub count: u64,
+    /// A value from 0 to 4 describing how busy this day was relative to the
+    /// busiest day in the heatmap.
+    pub level: u8,
+    /// True if this day is after the last day of the heatmap.
+    pub future: bool,
+}
+
+impl Heatmap {
+    /// Builds a calendar heatmap with `weeks` columns that ends on the week
+    /// containing `last_day`.
+    pub fn new(totals: &BTreeMap<NaiveDate, u64>, last_day: NaiveDate, weeks: u32) -> Self {
+        let first_day = last_day
+            - Duration::days(i64::from(last_day.weekday().num_days_from_sunday()))
+            - Duration::weeks(i64::from(weeks.saturating_sub(1)));
+        let busiest = totals
+            .range(first_day..=last_day)
+            .map(|(_, count)| *count)
+            .max()
+            .unwrap_or_default();
+
+        let mut heatmap = Self {
+            total: 0,
+            weeks: Vec::new(),
+        };
+        let mut day = first_day;
+        for _ in 0..weeks {
+            let mut week = Vec::with_capacity(7);
+            for _ in 0..7 {
+                let count = totals.get(&day).copied().unwrap_or_default();
+                heatmap.total += count;
+                week.push(HeatmapDay {
+                    iso_date: day.format("%Y-%m-%d").to_string(),
+                    count,
+                    level: level(count, busiest),
+                    future: day > last_day,
+                });
+                day = day.succ();
+            }
+            heatmap.weeks.push(week);
+        }
+
+        heatmap
+    }
+}
+
+/// Returns a value from 0 to 4 describing how large `count` is relative to
+/// `busiest`.
+fn level(count: u64, busiest: u64) -> u8 {
+    if count == 0 {
+        0
+    } else {
+        // Scale into 1..=4, rounding up.
+        (count * 4).div_ceil(busiest) as u8
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct MonthlyTotal {
+    /// The month, such as `March 2022`.
+    pub display: String,
+    pub count: u64,
+    /// A value from 0 to 4 describing how busy this month was relative to the
+    /// busiest month.
+    pub level: u8,
+}
+
+/// Sums `totals` by month, from the month of the first day with activity
+/// through the month containing `last_day`, most recent first.
+pub fn monthly_totals(totals: &BTreeMap<NaiveDate, u64>, last_day: NaiveDate) -> Vec<MonthlyTotal> {
+    let first_day = match totals.keys().next() {
+        Some(first_day) => first_day.with_day(1).unwrap(),
+        None => return Vec::new(),
+    };
+
+    let mut months = BTreeMap::<NaiveDate, u64>::new();
+    let mut month = first_day;
+    while month <= last_day {
+        months.insert(month, 0);
+        month = if month.month() == 12 {
+            NaiveDate::from_ymd(month.year() + 1, 1, 1)
+        } else {
+            NaiveDate::from_ymd(month.year(), month.month() + 1, 1)
+        };
+    }
+    for (day, count) in totals.range(first_day..=last_day) {
+        *months.entry(day.with_day(1).unwrap()).or_default() += count;
+    }
+
+    let busiest = months.values().copied().max().unwrap_or_default();
+    months
+        .into_iter()
+        .rev()
+        .map(|(month, count)| MonthlyTotal {
+            display: month.format("%B %Y").to_string(),
+            count,
+            level: level(count, busiest),
+        })
+        .collect()
+}
+
+/// The recent activity in a repository, shown on its project's page.
+#[derive(Serialize, Debug, Default)]
+pub struct RepositoryActivity {
+    pub releases: Vec<RecentRelease>,
+    pub issues: Vec<IssueActivity>,
+    pub contributors: Vec<Contributor>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RecentRelease {
+    pub date: String,
+    #[serde(flatten)]
+    pub release: Release,
+}
+
+/// An issue or pull request being opened, closed or reopened.
+#[derive(Serialize, Debug)]
+pub struct IssueActivity {
+    pub date: String,
+    pub pull_request: bool,
+    /// `opened`, `closed`, `reopened` or `merged`.
+    pub action: String,
+    pub number: u64,
+    pub title: String,
+    pub url: String,
+    pub author: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Contributor {
+    pub login: String,
+    pub events: u64,
+}
+
+/// Returns the recent releases, issue and pull request activity, and the
+/// most active contributors of `repository` (`owner/name`).
+pub async fn repository_activity<C: AsyncConnection>(
+    database: &C,
+    repository: &str,
+) -> anyhow::Result<RepositoryActivity> {
+    let all_time = SystemTime::UNIX_EPOCH..SystemTime::now();
+    let releases = api::recent_events(
+        database,
+        EventSource::Repository(repository),
+        all_time.clone(),
+        None,
+        RECENT_RELEASES,
+        |event| event.kind == "ReleaseEvent",
+    )
+    .await?
+    .into_iter()
+    .filter_map(|(_, event)| {
+        let mut payload = serde_json::value::from_value::<ReleasePayload>(event.payload).ok()?;
+        if payload.release.draft {
+            return None;
+        }
+        payload.release.short_description_html =
+            html::sanitize(&payload.release.short_description_html);
+        Some(RecentRelease {
+            date: event.created_at.format("%B %e, %Y").to_string(),
+            release: payload.release,
+        })
+    })
+    .collect();
+
+    let issues = api::recent_events(
+        database,
+        EventSource::Repository(repository),
+        all_time,
+        None,
+        RECENT_ISSUES,
+        |event| issue_activity(event).is_some(),
+    )
+    .await?
+    .into_iter()
+    .filter_map(|(_, event)| issue_activity(&event))
+    .collect();
+
+    let mut contributors = database
+        .view::<GitHubContributorCounts>()
+        .with_key(repository.to_string())
+        .reduce()
+        .await?
+        .into_iter()
+        .map(|(login, events)| Contributor { login, events })
+        .collect::<Vec<_>>();
+    contributors.sort_by(|a, b| b.events.cmp(&a.events).then_with(|| a.login.cmp(&b.login)));
+    contributors.truncate(TOP_CONTRIBUTORS);
+
+    Ok(RepositoryActivity {
+        releases,
+        issues,
+        contributors,
+    })
+}
+
+fn issue_activity(event: &Event) -> Option<IssueActivity> {
+    let date = event.created_at.format("%B %e, %Y").to_string();
+    match event.kind.as_str() {
+        "IssuesEvent" => {
+            let payload =
+                serde_json::value::from_value::<IssuesPayload>(event.payload.clone()).ok()?;
+            matches!(payload.action.as_str(), "opened" | "closed" | "reopened").then(|| {
+                IssueActivity {
+                    date,
+                    pull_request: false,
+                    action: payload.action,
+                    number: payload.issue.number,
+                    title: payload.issue.title,
+                    url: payload.issue.html_url,
+                    author: event.actor.login.clone(),
+                }
+            })
+        }
+        "PullRequestEvent" => {
+            let payload =
+                serde_json::value::from_value::<PullRequestPayload>(event.payload.clone()).ok()?;
+            let action = match payload.action.as_str() {
+                "closed" if payload.pull_request.merged => String::from("merged"),
+                "opened" | "closed" | "reopened" => payload.action,
+                _ => return None,
+            };
+            Some(IssueActivity {
+                date,
+                pull_request: true,
+                action,
+                number: payload.number,
+                title: payload.pull_request.title,
+                url: payload.pull_request.html_url,
+                author: event.actor.login.clone(),
+            })
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bonsaidb::core::schema::SerializedCollection;
+
+    use super::*;
+    use crate::{
+        database::{self, VaultKeyLocation},
+        testing::{event, issue, TestDirectory},
+    };
+
+    #[tokio::test]
+    async fn daily_totals_span_repositories() -> anyhow::Result<()> {
+        let directory = TestDirectory::new("activity-test");
+        let database = database::open_local(
+            &directory.join("projects.bonsaidb"),
+            &VaultKeyLocation::Storage,
+        )
+        .await?;
+
+        let events = [
+            ("khonsulabs/bonsaidb", "ecton", "2022-03-01T10:00:00Z"),
+            ("khonsulabs/nebari", "ecton", "2022-03-01T12:00:00Z"),
+            (
+                "khonsulabs/nebari",
+                "dependabot[bot]",
+                "2022-03-01T12:00:00Z",
+            ),
+            ("khonsulabs/bonsaidb", "ecton", "2022-03-02T08:00:00Z"),
+            ("khonsulabs/bonsaidb", "ecton", "2022-03-04T08:00:00Z"),
+        ];
+        for (id, (repository, login, created_at)) in events.into_iter().enumerate() {
+            let mut event = event(id as u64, "IssuesEvent", created_at, issue("opened", 1, ""));
+            event.repository.name = repository.to_string();
+            event.actor.login = login.to_string();
+            event.push_into_async(&database).await?;
+        }
+
+        let (from, to) = (
+            NaiveDate::from_ymd(2022, 3, 1),
+            NaiveDate::from_ymd(2022, 3, 2),
+        );
+        assert_eq!(
+            daily_totals(&database, None, from, to).await?,
+            BTreeMap::from([(from, 2), (to, 1)])
+        );
+        assert_eq!(
+            daily_totals(&database, Some("khonsulabs/nebari"), from, to).await?,
+            BTreeMap::from([(from, 1)])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn months_without_activity_are_included() {
+        let totals = BTreeMap::from([
+            (NaiveDate::from_ymd(2021, 11, 30), 2),
+            (NaiveDate::from_ymd(2022, 2, 1), 4),
+            (NaiveDate::from_ymd(2022, 2, 14), 4),
+        ]);
+        let months = monthly_totals(&totals, NaiveDate::from_ymd(2022, 3, 15));
+        assert_eq!(
+            months
+                .iter()
+                .map(|month| (month.display.as_str(), month.count, month.level))
+                .collect::<Vec<_>>(),
+            [
+                ("March 2022", 0, 0),
+                ("February 2022", 8, 4),
+                ("January 2022", 0, 0),
+                ("December 2021", 0, 0),
+                ("November 2021", 2, 1),
+            ]
+        );
+        assert!(monthly_totals(&BTreeMap::new(), NaiveDate::from_ymd(2022, 3, 15)).is_empty());
+    }
+}
@@ -0,0 +1,700 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{self, Display},
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+use axum::{
+    extract::{self, Extension},
+    response::{Headers, IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use bonsaidb::core::{
+    connection::{AsyncConnection, Range},
+    schema::SerializedCollection,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use http::{header, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    manifests::{self, ProjectCrates},
+    projects::{self, Lifecycle, Project, ProjectStatus},
+    schema::{Event, GitHubEventByActor, GitHubEventByRepository, GitHubEventByTimestamp},
+    summaries::DailySummary,
+};
+
+/// The OpenAPI document describing the endpoints in [`router()`].
+pub const OPENAPI_DOCUMENT: &str = include_str!("openapi.json");
+
+/// The number of items in a page when `limit` isn't given.
+const DEFAULT_LIMIT: usize = 50;
+
+/// The largest `limit` a page can be requested with.
+const MAX_LIMIT: usize = 100;
+
+/// Returns the routes of version 1 of the API, which is nested under
+/// `/api/v1`.
+pub fn router<C: AsyncConnection + Clone + 'static>() -> Router {
+    Router::new()
+        .route("/projects", get(projects_handler::<C>))
+        .route("/projects/:name", get(project_handler::<C>))
+        .route("/days", get(days_handler::<C>))
+        .route("/events", get(events_handler::<C>))
+        .route("/openapi.json", get(openapi_handler))
+}
+
+/// A page of results. If there are more results, `next_cursor` is passed as
+/// `cursor` to request the next page.
+#[derive(Serialize, Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    /// Returns the first `limit` of `items`. `items` should contain one more
+    /// result than `limit` if there are more results, in which case `cursor`
+    /// describes the last item returned.
+    fn new(mut items: Vec<T>, limit: usize, cursor: impl Fn(&T) -> String) -> Self {
+        let next_cursor = if items.len() > limit {
+            items.truncate(limit);
+            items.last().map(cursor)
+        } else {
+            None
+        };
+        Self { items, next_cursor }
+    }
+}
+
+/// An error returned as `{"error": "..."}`.
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    NotFound(String),
+    Internal(anyhow::Error),
+}
+
+impl<E: Into<anyhow::Error>> From<E> for ApiError {
+    fn from(err: E) -> Self {
+        Self::Internal(err.into())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, error) = match self {
+            ApiError::BadRequest(message) => (StatusCode::BAD_REQUEST, message),
+            ApiError::NotFound(message) => (StatusCode::NOT_FOUND, message),
+            ApiError::Internal(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+        };
+        (status, Json(ErrorBody { error })).into_response()
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorBody {
+    error: String,
+}
+
+/// Checks the requested page size, defaulting to [`DEFAULT_LIMIT`].
+fn page_limit(limit: Option<usize>) -> Result<usize, ApiError> {
+    match limit.unwrap_or(DEFAULT_LIMIT) {
+        limit @ 1..=MAX_LIMIT => Ok(limit),
+        _ => Err(ApiError::BadRequest(format!(
+            "limit must be between 1 and {}",
+            MAX_LIMIT
+        ))),
+    }
+}
+
+fn check_date_range(from: Option<NaiveDate>, to: Option<NaiveDate>) -> Result<(), ApiError> {
+    match (from, to) {
+        (Some(from), Some(to)) if from > to => Err(ApiError::BadRequest(String::from(
+            "from must not be after to",
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the first instant of `date`.
+pub fn start_of_day(date: NaiveDate) -> SystemTime {
+    SystemTime::from(DateTime::<Utc>::from_utc(date.and_hms(0, 0, 0), Utc))
+}
+
+/// Returns the first instant after `date`. Unlike the start of the next day,
+/// this exists for the last date chrono can represent.
+pub fn end_of_day(date: NaiveDate) -> SystemTime {
+    start_of_day(date) + Duration::from_secs(24 * 60 * 60)
+}
+
+/// A project in the catalog along with its lifecycle.
+#[derive(Serialize, Debug)]
+pub struct ProjectResource {
+    pub key: String,
+    #[serde(flatten)]
+    pub project: Project,
+    pub lifecycle: Lifecycle,
+}
+
+/// A project along with the crates it shares with other projects.
+#[derive(Serialize, Debug)]
+pub struct ProjectDetails {
+    #[serde(flatten)]
+    pub resource: ProjectResource,
+    pub crates: ProjectCrates,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ProjectsQuery {
+    pub status: Option<ProjectStatus>,
+    pub cursor: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// Returns a page of the catalog, ordered by key. The cursor is the key of the
+/// last project returned.
+pub async fn projects<C: AsyncConnection>(
+    database: &C,
+    query: &ProjectsQuery,
+) -> Result<Page<ProjectResource>, ApiError> {
+    let limit = page_limit(query.limit)?;
+    let page = projects::catalog(database)
+        .await?
+        .into_iter()
+        .filter(|(key, project)| {
+            query.cursor.as_ref().is_none_or(|cursor| key > cursor)
+                && query.status.is_none_or(|status| project.status == status)
+        })
+        .take(limit + 1)
+        .collect::<BTreeMap<_, _>>();
+    let mut lifecycles = projects::lifecycles(database, &page).await?;
+    let items = page
+        .into_iter()
+        .filter_map(|(key, project)| {
+            let lifecycle = lifecycles.remove(&key)?;
+            Some(ProjectResource {
+                key,
+                project,
+                lifecycle,
+            })
+        })
+        .collect();
+    Ok(Page::new(items, limit, |project| project.key.clone()))
+}
+
+/// Returns the project whose key is `name`, ignoring case.
+pub async fn project<C: AsyncConnection>(
+    database: &C,
+    name: &str,
+) -> Result<ProjectDetails, ApiError> {
+    let key = name.to_ascii_lowercase();
+    let project = Project::get_async(&key, database)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("no project named {:?}", name)))?
+        .contents;
+    let mut catalog = BTreeMap::new();
+    catalog.insert(key.clone(), project);
+    let lifecycle = projects::lifecycles(database, &catalog)
+        .await?
+        .remove(&key)
+        .expect("lifecycle missing");
+    let crates = manifests::crate_graph(database)
+        .await?
+        .remove(&key)
+        .unwrap_or_default();
+    Ok(ProjectDetails {
+        resource: ProjectResource {
+            project: catalog.remove(&key).expect("project missing"),
+            key,
+            lifecycle,
+        },
+        crates,
+    })
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct DaysQuery {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    /// Only include this repository (`owner/name` or `name`).
+    pub repository: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// Returns a page of the daily summaries between `from` and `to` (inclusive,
+/// defaulting to every day until today) that had activity, most recent
+/// first. The cursor is the date of the last day returned.
+pub async fn days<C: AsyncConnection>(
+    database: &C,
+    query: &DaysQuery,
+) -> Result<Page<DailySummary>, ApiError> {
+    let limit = page_limit(query.limit)?;
+    check_date_range(query.from, query.to)?;
+    let mut to = query.to.unwrap_or_else(|| Utc::today().naive_utc());
+    if let Some(cursor) = &query.cursor {
+        let cursor = NaiveDate::parse_from_str(cursor, "%Y-%m-%d")
+            .map_err(|_| ApiError::BadRequest(format!("invalid cursor {:?}", cursor)))?;
+        match cursor.pred_opt() {
+            Some(before) => to = to.min(before),
+            None => {
+                return Ok(Page {
+                    items: Vec::new(),
+                    next_cursor: None,
+                })
+            }
+        }
+    }
+    if query.from.is_some_and(|from| from > to) {
+        return Ok(Page {
+            items: Vec::new(),
+            next_cursor: None,
+        });
+    }
+
+    let from = query.from.map(|from| from.format("%Y-%m-%d").to_string());
+    let end = to.succ_opt().map(|end| end.format("%Y-%m-%d").to_string());
+    let range: Range<String> = match (from, end) {
+        (Some(from), Some(end)) => (from..end).into(),
+        (Some(from), None) => (from..).into(),
+        (None, Some(end)) => (..end).into(),
+        (None, None) => (..).into(),
+    };
+    let repository = query
+        .repository
+        .as_deref()
+        .map(|repository| repository.rsplit('/').next().unwrap_or(repository));
+    let mut list = DailySummary::list_async(range, database).descending();
+    if repository.is_none() {
+        list = list.limit(u32::try_from(limit + 1)?);
+    }
+
+    let mut items = Vec::new();
+    for document in list.await? {
+        let mut summary = document.contents;
+        if let Some(repository) = repository {
+            summary.repositories.retain(|name, _| name == repository);
+            if summary.repositories.is_empty() {
+                continue;
+            }
+        }
+        items.push(summary);
+        if items.len() > limit {
+            break;
+        }
+    }
+    Ok(Page::new(items, limit, |day| day.iso_date.clone()))
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct EventsQuery {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    /// Only include events in this repository (`owner/name`).
+    pub repository: Option<String>,
+    /// Only include events caused by this user.
+    pub actor: Option<String>,
+    /// Only include events of this type, such as `PushEvent`.
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// The position of an event in the results of [`recent_events()`]: the time
+/// it was created, and its document id to order events created at the same
+/// time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct EventCursor {
+    created_at: SystemTime,
+    id: u64,
+}
+
+impl Display for EventCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let nanos = self
+            .created_at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        write!(f, "{}-{}", nanos, self.id)
+    }
+}
+
+impl FromStr for EventCursor {
+    type Err = ApiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split_once('-')
+            .and_then(|(nanos, id)| {
+                Some(Self {
+                    created_at: SystemTime::UNIX_EPOCH + Duration::from_nanos(nanos.parse().ok()?),
+                    id: id.parse().ok()?,
+                })
+            })
+            .ok_or_else(|| ApiError::BadRequest(format!("invalid cursor {:?}", s)))
+    }
+}
+
+/// The view [`recent_events()`] reads events from.
+#[derive(Debug, Clone, Copy)]
+pub enum EventSource<'a> {
+    All,
+    /// The events in a repository (`owner/name`).
+    Repository(&'a str),
+    /// The events caused by a user.
+    Actor(&'a str),
+}
+
+/// Returns up to `limit` events from `source` that were created within
+/// `range` and before `cursor`, most recent first, skipping events that
+/// `matches` returns false for.
+pub async fn recent_events<C: AsyncConnection>(
+    database: &C,
+    source: EventSource<'_>,
+    range: std::ops::Range<SystemTime>,
+    cursor: Option<EventCursor>,
+    limit: usize,
+    matches: impl Fn(&Event) -> bool,
+) -> anyhow::Result<Vec<(EventCursor, Event)>> {
+    let batch_size = u32::try_from(limit)?;
+    let mut end = range.end;
+    if let Some(cursor) = cursor {
+        end = end.min(cursor.created_at + Duration::from_nanos(1));
+    }
+
+    let mut events = Vec::new();
+    while range.start < end {
+        // Only the view entries are loaded up front, so that events are
+        // ordered and the cursor applied before any documents are read. Each
+        // batch reads the most recent `limit` timestamps before `end`, along
+        // with every event created at them.
+        let (mut positions, timestamps) =
+            positions_before(database, source, range.start..end, batch_size).await?;
+        if let Some(oldest) = positions.iter().map(|position| position.created_at).min() {
+            end = oldest;
+        }
+        positions.retain(|position| cursor.is_none_or(|cursor| *position < cursor));
+        positions.sort_unstable_by(|a, b| b.cmp(a));
+
+        let mut documents =
+            Event::get_multiple_async(positions.iter().map(|position| position.id), database)
+                .await?
+                .into_iter()
+                .map(|document| (document.header.id, document.contents))
+                .collect::<HashMap<_, _>>();
+        for position in positions {
+            let event = match documents.remove(&position.id) {
+                Some(event) => event,
+                None => continue,
+            };
+            if matches(&event) {
+                events.push((position, event));
+                if events.len() == limit {
+                    return Ok(events);
+                }
+            }
+        }
+
+        if timestamps < limit {
+            break;
+        }
+    }
+    Ok(events)
+}
+
+/// Returns the positions of the events from `source` created at the most
+/// recent `timestamps` distinct times within `range`, along with the number of
+/// distinct times that were found.
+async fn positions_before<C: AsyncConnection>(
+    database: &C,
+    source: EventSource<'_>,
+    range: std::ops::Range<SystemTime>,
+    timestamps: u32,
+) -> anyhow::Result<(Vec<EventCursor>, usize)> {
+    let (start, end) = (range.start, range.end);
+    let mappings = match source {
+        EventSource::Repository(repository) => database
+            .view::<GitHubEventByRepository>()
+            .with_key_range((repository.to_string(), start)..(repository.to_string(), end))
+            .descending()
+            .limit(timestamps)
+            .query()
+            .await?
+            .into_iter()
+            .map(|mapping| (mapping.key.1, mapping.source.id))
+            .collect::<Vec<_>>(),
+        EventSource::Actor(actor) => database
+            .view::<GitHubEventByActor>()
+            .with_key_range((actor.to_string(), start)..(actor.to_string(), end))
+            .descending()
+            .limit(timestamps)
+            .query()
+            .await?
+            .into_iter()
+            .map(|mapping| (mapping.key.1, mapping.source.id))
+            .collect(),
+        EventSource::All => database
+            .view::<GitHubEventByTimestamp>()
+            .with_key_range(start..end)
+            .descending()
+            .limit(timestamps)
+            .query()
+            .await?
+            .into_iter()
+            .map(|mapping| (mapping.key, mapping.source.id))
+            .collect(),
+    };
+    let mut distinct = mappings
+        .iter()
+        .map(|(created_at, _)| *created_at)
+        .collect::<Vec<_>>();
+    distinct.dedup();
+    let positions = mappings
+        .into_iter()
+        .map(|(created_at, id)| {
+            Ok(EventCursor {
+                created_at,
+                id: id.deserialize()?,
+            })
+        })
+        .collect::<Result<Vec<_>, bonsaidb::core::Error>>()?;
+    Ok((positions, distinct.len()))
+}
+
+/// Returns a page of the stored events between `from` and `to` (inclusive),
+/// most recent first. The cursor identifies the last event returned.
+pub async fn events<C: AsyncConnection>(
+    database: &C,
+    query: &EventsQuery,
+) -> Result<Page<Event>, ApiError> {
+    let limit = page_limit(query.limit)?;
+    check_date_range(query.from, query.to)?;
+    let cursor = query
+        .cursor
+        .as_deref()
+        .map(EventCursor::from_str)
+        .transpose()?;
+    let source = match (&query.repository, &query.actor) {
+        (Some(repository), _) => EventSource::Repository(repository),
+        (None, Some(actor)) => EventSource::Actor(actor),
+        (None, None) => EventSource::All,
+    };
+    let start = query.from.map_or(SystemTime::UNIX_EPOCH, start_of_day);
+    let end = end_of_day(query.to.unwrap_or_else(|| Utc::today().naive_utc()));
+    let items = recent_events(database, source, start..end, cursor, limit + 1, |event| {
+        query
+            .actor
+            .as_ref()
+            .is_none_or(|actor| &event.actor.login == actor)
+            && query.kind.as_ref().is_none_or(|kind| &event.kind == kind)
+    })
+    .await?;
+    let page = Page::new(items, limit, |(position, _)| position.to_string());
+    Ok(Page {
+        items: page.items.into_iter().map(|(_, event)| event).collect(),
+        next_cursor: page.next_cursor,
+    })
+}
+
+async fn projects_handler<C: AsyncConnection + Clone + 'static>(
+    database: Extension<C>,
+    extract::Query(query): extract::Query<ProjectsQuery>,
+) -> Result<Json<Page<ProjectResource>>, ApiError> {
+    projects(&*database, &query).await.map(Json)
+}
+
+async fn project_handler<C: AsyncConnection + Clone + 'static>(
+    database: Extension<C>,
+    extract::Path(name): extract::Path<String>,
+) -> Result<Json<ProjectDetails>, ApiError> {
+    project(&*database, &name).await.map(Json)
+}
+
+async fn days_handler<C: AsyncConnection + Clone + 'static>(
+    database: Extension<C>,
+    extract::Query(query): extract::Query<DaysQuery>,
+) -> Result<Json<Page<DailySummary>>, ApiError> {
+    days(&*database, &query).await.map(Json)
+}
+
+async fn events_handler<C: AsyncConnection + Clone + 'static>(
+    database: Extension<C>,
+    extract::Query(query): extract::Query<EventsQuery>,
+) -> Result<Json<Page<Event>>, ApiError> {
+    events(&*database, &query).await.map(Json)
+}
+
+async fn openapi_handler() -> impl IntoResponse {
+    (
+        Headers([(header::CONTENT_TYPE, "application/json")]),
+        OPENAPI_DOCUMENT,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use transmog_json::serde_json::{self, Value};
+
+    use super::*;
+    use crate::{
+        backup::SnapshotLock,
+        config::ActivitySection,
+        database::{self, VaultKeyLocation},
+        testing::{event, issue, TestDirectory},
+        updater,
+    };
+
+    #[tokio::test]
+    async fn pages_cover_every_item_once() -> anyhow::Result<()> {
+        let directory = TestDirectory::new("api-test");
+        let database = database::open_local(
+            &directory.join("projects.bonsaidb"),
+            &VaultKeyLocation::Storage,
+        )
+        .await?;
+
+        // Several events share a timestamp, so the cursor has to order them
+        // by more than their time.
+        let times = [
+            "2022-03-01T10:00:00Z",
+            "2022-03-01T10:00:00Z",
+            "2022-03-01T10:00:00Z",
+            "2022-03-02T08:00:00Z",
+            "2022-03-02T08:00:00Z",
+            "2022-03-03T12:00:00Z",
+            "2022-03-05T12:00:00Z",
+        ];
+        for (id, created_at) in times.iter().enumerate() {
+            let kind = if id % 2 == 0 {
+                "IssuesEvent"
+            } else {
+                "WatchEvent"
+            };
+            let event = event(
+                id as u64,
+                kind,
+                created_at,
+                issue("closed", id as u64, "An issue"),
+            );
+            updater::insert_event(
+                &database,
+                event,
+                &ActivitySection::default(),
+                &SnapshotLock::default(),
+            )
+            .await?;
+        }
+
+        let mut query = EventsQuery {
+            to: NaiveDate::from_ymd_opt(2022, 3, 31),
+            limit: Some(2),
+            ..EventsQuery::default()
+        };
+        let mut ids = Vec::new();
+        loop {
+            let page = events(&database, &query).await.unwrap();
+            assert!(page.items.len() <= 2);
+            ids.extend(page.items.into_iter().map(|event| event.id));
+            match page.next_cursor {
+                Some(cursor) => query.cursor = Some(cursor),
+                None => break,
+            }
+        }
+        assert_eq!(ids.len(), times.len());
+        assert_eq!(ids.iter().collect::<BTreeSet<_>>().len(), times.len());
+        assert_eq!(ids.first().map(String::as_str), Some("6"));
+
+        query.cursor = None;
+        query.kind = Some(String::from("IssuesEvent"));
+        query.limit = Some(3);
+        let page = events(&database, &query).await.unwrap();
+        assert_eq!(
+            page.items.iter().map(|event| &event.id).collect::<Vec<_>>(),
+            ["6", "4", "2"]
+        );
+        query.cursor = page.next_cursor;
+        let page = events(&database, &query).await.unwrap();
+        assert_eq!(
+            page.items.iter().map(|event| &event.id).collect::<Vec<_>>(),
+            ["0"]
+        );
+        assert!(page.next_cursor.is_none());
+
+        let mut query = DaysQuery {
+            to: NaiveDate::from_ymd_opt(2022, 3, 31),
+            repository: Some(String::from("khonsulabs/bonsaidb")),
+            limit: Some(2),
+            ..DaysQuery::default()
+        };
+        let page = days(&database, &query).await.unwrap();
+        assert_eq!(
+            page.items
+                .iter()
+                .map(|day| day.iso_date.as_str())
+                .collect::<Vec<_>>(),
+            ["2022-03-05", "2022-03-02"]
+        );
+        query.cursor = page.next_cursor;
+        let page = days(&database, &query).await.unwrap();
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.items[0].iso_date, "2022-03-01");
+        assert!(page.next_cursor.is_none());
+
+        query.limit = Some(MAX_LIMIT + 1);
+        assert!(matches!(
+            days(&database, &query).await,
+            Err(ApiError::BadRequest(_))
+        ));
+        query.limit = None;
+        query.cursor = Some(String::from("yesterday"));
+        assert!(matches!(
+            days(&database, &query).await,
+            Err(ApiError::BadRequest(_))
+        ));
+
+        // The ends of chrono's calendar have no neighboring days.
+        query.to = Some(chrono::naive::MAX_DATE);
+        query.cursor = None;
+        assert_eq!(days(&database, &query).await.unwrap().items.len(), 3);
+        query.cursor = Some(chrono::naive::MIN_DATE.format("%Y-%m-%d").to_string());
+        assert!(days(&database, &query).await.unwrap().items.is_empty());
+        let query = EventsQuery {
+            to: Some(chrono::naive::MAX_DATE),
+            ..EventsQuery::default()
+        };
+        assert_eq!(
+            events(&database, &query).await.unwrap().items.len(),
+            times.len()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn openapi_document_describes_every_route() {
+        let document: Value = serde_json::from_str(OPENAPI_DOCUMENT).unwrap();
+        let paths = document["paths"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<BTreeSet<_>>();
+        // The router's paths, written with OpenAPI's path parameters.
+        let routes = [
+            "/projects",
+            "/projects/{name}",
+            "/days",
+            "/events",
+            "/openapi.json",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect::<BTreeSet<_>>();
+        assert_eq!(paths, routes);
+    }
+}
@@ -0,0 +1,328 @@
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+use serde::Serialize;
+
+/// The most days a single activity page loads. Longer ranges are cut short,
+/// and the page's previous link continues with the days that were left out.
+pub const MAX_PAGE_DAYS: i64 = 92;
+
+/// The number of days shown when no range is requested.
+pub const RECENT_DAYS: i64 = 28;
+
+/// A span of days whose activity is shown on one page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    /// An inclusive range of days, requested with `?from=` and `?to=`.
+    Days { from: NaiveDate, to: NaiveDate },
+    /// A calendar month, requested as `/activity/{year}/{month}`.
+    Month { year: i32, month: u32 },
+    /// An ISO 8601 week, requested as `/activity/{year}-W{week}`.
+    Week { year: i32, week: u32 },
+}
+
+impl Period {
+    /// Returns the most recent [`RECENT_DAYS`] ending on `today`.
+    pub fn recent(today: NaiveDate) -> Self {
+        Self::Days {
+            from: today - Duration::days(RECENT_DAYS - 1),
+            to: today,
+        }
+    }
+
+    /// Returns the month, if `month` is between 1 and 12 and all of its days
+    /// can be represented.
+    pub fn month(year: i32, month: u32) -> Option<Self> {
+        NaiveDate::from_ymd_opt(year, month, 1)?;
+        NaiveDate::from_ymd_opt(year, month, 28).map(|_| Self::Month { year, month })
+    }
+
+    /// Returns the ISO week, if it exists and all of its days can be
+    /// represented.
+    pub fn week(year: i32, week: u32) -> Option<Self> {
+        NaiveDate::from_isoywd_opt(year, week, Weekday::Mon)?;
+        NaiveDate::from_isoywd_opt(year, week, Weekday::Sun).map(|_| Self::Week { year, week })
+    }
+
+    /// Parses an ISO week written as `2022-W10`.
+    pub fn parse_week(week: &str) -> Option<Self> {
+        let (year, week) = week.split_once("-W")?;
+        Self::week(year.parse().ok()?, week.parse().ok()?)
+    }
+
+    pub fn first_day(&self) -> NaiveDate {
+        match *self {
+            Period::Days { from, .. } => from,
+            Period::Month { year, month } => NaiveDate::from_ymd(year, month, 1),
+            Period::Week { year, week } => NaiveDate::from_isoywd(year, week, Weekday::Mon),
+        }
+    }
+
+    pub fn last_day(&self) -> NaiveDate {
+        match *self {
+            Period::Days { to, .. } => to,
+            Period::Month { year, month } => (28..=31)
+                .rev()
+                .find_map(|day| NaiveDate::from_ymd_opt(year, month, day))
+                .unwrap_or_else(|| self.first_day()),
+            Period::Week { year, week } => NaiveDate::from_isoywd(year, week, Weekday::Sun),
+        }
+    }
+
+    /// Returns the period of the same kind and length that ends the day
+    /// before this one starts, if all of its days can be represented.
+    pub fn previous(&self) -> Option<Self> {
+        match *self {
+            Period::Days { from, to } => {
+                let previous_to = from.pred_opt()?;
+                Some(Period::Days {
+                    from: previous_to.checked_sub_signed(to - from)?,
+                    to: previous_to,
+                })
+            }
+            Period::Month { year, month: 1 } => Self::month(year - 1, 12),
+            Period::Month { year, month } => Self::month(year, month - 1),
+            Period::Week { .. } => {
+                let week = self.first_day().pred_opt()?.iso_week();
+                Self::week(week.year(), week.week())
+            }
+        }
+    }
+
+    /// Returns the period of the same kind and length that starts the day
+    /// after this one ends, if all of its days can be represented.
+    pub fn next(&self) -> Option<Self> {
+        match *self {
+            Period::Days { from, to } => {
+                let next_from = to.succ_opt()?;
+                Some(Period::Days {
+                    from: next_from,
+                    to: next_from.checked_add_signed(to - from)?,
+                })
+            }
+            Period::Month { year, month: 12 } => Self::month(year + 1, 1),
+            Period::Month { year, month } => Self::month(year, month + 1),
+            Period::Week { .. } => {
+                let week = self.last_day().succ_opt()?.iso_week();
+                Self::week(week.year(), week.week())
+            }
+        }
+    }
+
+    /// Returns the ISO week that contains `date`.
+    pub fn week_containing(date: NaiveDate) -> Self {
+        let week = date.iso_week();
+        Period::Week {
+            year: week.year(),
+            week: week.week(),
+        }
+    }
+
+    /// Returns the month that contains `date`.
+    pub fn month_containing(date: NaiveDate) -> Self {
+        Period::Month {
+            year: date.year(),
+            month: date.month(),
+        }
+    }
+
+    /// Returns the path of the page showing this period.
+    pub fn path(&self) -> String {
+        match *self {
+            Period::Days { from, to } => format!(
+                "/?from={}&to={}",
+                from.format("%Y-%m-%d"),
+                to.format("%Y-%m-%d")
+            ),
+            Period::Month { year, month } => format!("/activity/{}/{:02}", year, month),
+            Period::Week { year, week } => format!("/activity/{}-W{:02}", year, week),
+        }
+    }
+
+    pub fn title(&self) -> String {
+        match *self {
+            Period::Days { from, to } if from == to => from.format("%A, %B %-d, %Y").to_string(),
+            Period::Days { from, to } => format!(
+                "{} to {}",
+                from.format("%B %-d, %Y"),
+                to.format("%B %-d, %Y")
+            ),
+            Period::Month { .. } => self.first_day().format("%B %Y").to_string(),
+            Period::Week { year, week } => format!(
+                "Week {} of {} ({} to {})",
+                week,
+                year,
+                self.first_day().format("%B %-d"),
+                self.last_day().format("%B %-d")
+            ),
+        }
+    }
+}
+
+/// The range of days loaded for a [`Period`] and the links to the pages
+/// around it.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct ArchivePage {
+    pub title: String,
+    pub from: String,
+    pub to: String,
+    /// True when the period was longer than [`MAX_PAGE_DAYS`] and only its
+    /// most recent days are shown.
+    pub truncated: bool,
+    pub previous: Option<String>,
+    pub next: Option<String>,
+    #[serde(skip)]
+    pub first_day: NaiveDate,
+    #[serde(skip)]
+    pub last_day: NaiveDate,
+}
+
+impl ArchivePage {
+    /// Limits `period` to [`MAX_PAGE_DAYS`] and links to the neighboring
+    /// periods. There's no link to periods that start after `today`, that
+    /// end before `oldest`, the first day with stored activity, or that fall
+    /// outside the dates chrono can represent.
+    pub fn new(period: Period, today: NaiveDate, oldest: Option<NaiveDate>) -> Self {
+        let last_day = period.last_day();
+        let mut first_day = period.first_day();
+        let mut previous = period.previous();
+        let truncated = (last_day - first_day).num_days() >= MAX_PAGE_DAYS;
+        if truncated {
+            let remaining_to = last_day - Duration::days(MAX_PAGE_DAYS);
+            previous = Some(Period::Days {
+                from: first_day,
+                to: remaining_to,
+            });
+            first_day = remaining_to + Duration::days(1);
+        }
+
+        Self {
+            title: period.title(),
+            from: first_day.format("%Y-%m-%d").to_string(),
+            to: last_day.format("%Y-%m-%d").to_string(),
+            truncated,
+            previous: previous
+                .filter(|previous| oldest.is_some_and(|oldest| previous.last_day() >= oldest))
+                .map(|previous| previous.path()),
+            next: period
+                .next()
+                .filter(|next| next.first_day() <= today)
+                .map(|next| next.path()),
+            first_day,
+            last_day,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::naive::{MAX_DATE, MIN_DATE};
+
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd(year, month, day)
+    }
+
+    #[test]
+    fn periods_navigate_to_their_neighbors() {
+        let december = Period::month(2021, 12).unwrap();
+        assert_eq!(december.last_day(), date(2021, 12, 31));
+        assert_eq!(december.next(), Period::month(2022, 1));
+        assert_eq!(december.next().unwrap().previous(), Some(december));
+        assert_eq!(december.path(), "/activity/2021/12");
+        assert_eq!(Period::month(2022, 13), None);
+
+        let week = Period::parse_week("2022-W10").unwrap();
+        assert_eq!(week.first_day(), date(2022, 3, 7));
+        assert_eq!(week.last_day(), date(2022, 3, 13));
+        assert_eq!(week.path(), "/activity/2022-W10");
+        assert_eq!(
+            Period::parse_week("2022-W01").unwrap().previous(),
+            Period::parse_week("2021-W52")
+        );
+        assert_eq!(Period::parse_week("2022-W60"), None);
+        assert_eq!(Period::parse_week("2022-10"), None);
+
+        let days = Period::Days {
+            from: date(2022, 3, 1),
+            to: date(2022, 3, 10),
+        };
+        assert_eq!(
+            days.previous(),
+            Some(Period::Days {
+                from: date(2022, 2, 19),
+                to: date(2022, 2, 28),
+            })
+        );
+        assert_eq!(days.next().unwrap().first_day(), date(2022, 3, 11));
+        assert_eq!(days.path(), "/?from=2022-03-01&to=2022-03-10");
+    }
+
+    #[test]
+    fn pages_are_limited_and_bounded() {
+        let today = date(2022, 3, 15);
+        let page = ArchivePage::new(Period::month(2022, 3).unwrap(), today, None);
+        assert!(!page.truncated);
+        assert_eq!(page.previous, None);
+        assert_eq!(page.next, None);
+
+        let page = ArchivePage::new(
+            Period::month(2022, 2).unwrap(),
+            today,
+            Some(date(2022, 1, 31)),
+        );
+        assert_eq!(page.previous.as_deref(), Some("/activity/2022/01"));
+        assert_eq!(page.next.as_deref(), Some("/activity/2022/03"));
+
+        let page = ArchivePage::new(
+            Period::Days {
+                from: date(2021, 1, 1),
+                to: date(2021, 12, 31),
+            },
+            today,
+            Some(date(2020, 1, 1)),
+        );
+        assert!(page.truncated);
+        assert_eq!(
+            (page.last_day - page.first_day).num_days() + 1,
+            MAX_PAGE_DAYS
+        );
+        assert_eq!(
+            page.previous.as_deref(),
+            Some("/?from=2021-01-01&to=2021-09-30")
+        );
+    }
+
+    #[test]
+    fn periods_at_the_limits_have_no_neighbors() {
+        let last_month = Period::month(MAX_DATE.year(), 12).unwrap();
+        assert_eq!(last_month.last_day(), MAX_DATE);
+        assert_eq!(last_month.next(), None);
+        assert!(last_month.previous().is_some());
+        assert_eq!(Period::month(MAX_DATE.year() + 1, 1), None);
+
+        let first_month = Period::month(MIN_DATE.year(), 1).unwrap();
+        assert_eq!(first_month.previous(), None);
+
+        let days = Period::Days {
+            from: MAX_DATE - Duration::days(10),
+            to: MAX_DATE,
+        };
+        assert_eq!(days.next(), None);
+        let days = Period::Days {
+            from: MIN_DATE,
+            to: MIN_DATE + Duration::days(10),
+        };
+        assert_eq!(days.previous(), None);
+        let days = Period::Days {
+            from: MIN_DATE + Duration::days(5),
+            to: MIN_DATE + Duration::days(10),
+        };
+        assert_eq!(days.previous(), None);
+
+        let page = ArchivePage::new(last_month, MAX_DATE, Some(MIN_DATE));
+        assert_eq!(page.next, None);
+        assert!(page.previous.is_some());
+        let page = ArchivePage::new(first_month, MAX_DATE, Some(MIN_DATE));
+        assert_eq!(page.previous, None);
+    }
+}
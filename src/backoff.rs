@@ -0,0 +1,35 @@
+use std::time::Duration;
+
+/// The delay before the first retry.
+const INITIAL_DELAY: Duration = Duration::from_secs(1);
+
+/// The longest delay between retries.
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Doubles the delay between attempts to restore something that keeps
+/// failing, such as a PubSub subscription, up to [`MAX_DELAY`].
+#[derive(Debug)]
+pub struct Backoff {
+    delay: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            delay: INITIAL_DELAY,
+        }
+    }
+}
+
+impl Backoff {
+    /// Starts over from [`INITIAL_DELAY`] once an attempt succeeds.
+    pub fn reset(&mut self) {
+        self.delay = INITIAL_DELAY;
+    }
+
+    /// Waits before the next attempt, and doubles the delay for the one after.
+    pub async fn wait(&mut self) {
+        tokio::time::sleep(self.delay).await;
+        self.delay = (self.delay * 2).min(MAX_DELAY);
+    }
+}
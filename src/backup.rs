@@ -0,0 +1,810 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::DirBuilder,
+    os::unix::fs::DirBuilderExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Context;
+use aws_config::meta::region::RegionProviderChain;
+use aws_sdk_s3::{types::ByteStream, Client, Endpoint, Region};
+use bonsaidb::{
+    core::{
+        connection::{AsyncConnection, AsyncStorageConnection},
+        schema::{Qualified, Schema, Schematic, SerializedCollection},
+    },
+    local::{AsyncDatabase, AsyncStorage, BackupLocation, StorageNonBlocking},
+};
+use chrono::{DateTime, Utc};
+use http::Uri;
+use serde::{Deserialize, Serialize};
+use structopt::StructOpt;
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use transmog_json::serde_json;
+
+use crate::{
+    config::{BackupSection, Config},
+    database::{self, VaultKeyLocation, DATABASE_NAME},
+    manifests::RepositoryManifest,
+    projects::Project,
+    schema::{Event, Projects, SearchEntry},
+    search,
+    shutdown::Shutdown,
+    summaries::DailySummary,
+};
+
+/// Where snapshots are stored, how many are kept and how often they are
+/// taken.
+#[derive(Debug)]
+pub struct BackupConfiguration {
+    pub target: BackupTarget,
+    pub retention: usize,
+    pub interval: Duration,
+}
+
+impl BackupConfiguration {
+    /// Returns `None` if neither `directory` nor `s3_bucket` is set.
+    pub fn from_config(backup: &BackupSection) -> anyhow::Result<Option<Self>> {
+        let target = match (&backup.directory, &backup.s3_bucket) {
+            (Some(_), Some(_)) => {
+                anyhow::bail!("only one of directory and s3_bucket may be set")
+            }
+            (Some(path), None) => BackupTarget::Directory { path: path.clone() },
+            (None, Some(bucket)) => BackupTarget::S3 {
+                bucket: bucket.clone(),
+                endpoint: backup
+                    .s3_endpoint
+                    .as_deref()
+                    .map(|endpoint| {
+                        Uri::try_from(endpoint)
+                            .with_context(|| format!("invalid s3_endpoint {:?}", endpoint))
+                    })
+                    .transpose()?,
+                path: backup.s3_path.clone().unwrap_or_default(),
+            },
+            (None, None) => return Ok(None),
+        };
+        if backup.retention == 0 {
+            anyhow::bail!("retention must be at least 1");
+        }
+        if backup.interval_hours == 0 {
+            anyhow::bail!("interval_hours must be at least 1");
+        }
+
+        Ok(Some(Self {
+            target,
+            retention: backup.retention,
+            interval: Duration::from_secs(backup.interval_hours * 60 * 60),
+        }))
+    }
+}
+
+/// A location snapshots are stored in. Each snapshot is named after the time
+/// it was taken, and consists of a directory of documents and a
+/// `<name>.json` manifest beside it. The manifest is written last, so
+/// snapshots without one are incomplete and are ignored.
+#[derive(Debug)]
+pub enum BackupTarget {
+    Directory {
+        path: PathBuf,
+    },
+    /// An S3-compatible bucket, such as MinIO when `endpoint` is set.
+    /// Credentials are read from the environment.
+    S3 {
+        bucket: String,
+        endpoint: Option<Uri>,
+        /// The prefix of every key.
+        path: String,
+    },
+}
+
+/// The contents of a snapshot, used to verify it when it is restored.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SnapshotManifest {
+    pub created_at: DateTime<Utc>,
+    /// The number of documents in each collection of the database, keyed by
+    /// the encoded collection name.
+    pub documents: BTreeMap<String, usize>,
+}
+
+impl SnapshotManifest {
+    /// Counts the documents in the backup at `directory`.
+    pub fn read(directory: &Path, created_at: DateTime<Utc>) -> anyhow::Result<Self> {
+        let schema = Projects::schema_name();
+        let mut documents = BTreeMap::new();
+        for collection in Schematic::from_schema::<Projects>()?.collections() {
+            let encoded = collection.encoded();
+            let stored = BackupLocation::list_stored(directory, &schema, DATABASE_NAME, &encoded)?;
+            documents.insert(encoded, stored.len());
+        }
+        Ok(Self {
+            created_at,
+            documents,
+        })
+    }
+
+    fn documents_in<C: SerializedCollection>(&self) -> usize {
+        self.documents
+            .get(&C::collection_name().encoded())
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl BackupTarget {
+    /// Lists the names of the complete snapshots, oldest first.
+    pub async fn list_snapshots(&self) -> anyhow::Result<Vec<String>> {
+        let mut snapshots = Vec::new();
+        match self {
+            BackupTarget::Directory { path } => {
+                if path.exists() {
+                    for entry in std::fs::read_dir(path)? {
+                        let file_name = entry?.file_name();
+                        if let Some(name) = file_name
+                            .to_str()
+                            .and_then(|name| name.strip_suffix(".json"))
+                        {
+                            if path.join(name).is_dir() {
+                                snapshots.push(name.to_string());
+                            }
+                        }
+                    }
+                }
+            }
+            BackupTarget::S3 { .. } => {
+                let prefix = self.s3_key("");
+                for key in self.list_objects(&prefix, Some("/")).await? {
+                    if let Some(name) = key
+                        .strip_prefix(&prefix)
+                        .and_then(|name| name.strip_suffix(".json"))
+                    {
+                        snapshots.push(name.to_string());
+                    }
+                }
+            }
+        }
+        snapshots.sort();
+        Ok(snapshots)
+    }
+
+    /// Removes the snapshot `name`, starting with its manifest.
+    pub async fn delete_snapshot(&self, name: &str) -> anyhow::Result<()> {
+        match self {
+            BackupTarget::Directory { path } => {
+                std::fs::remove_file(path.join(format!("{}.json", name)))?;
+                std::fs::remove_dir_all(path.join(name))?;
+            }
+            BackupTarget::S3 { bucket, .. } => {
+                let client = self.s3_client().await;
+                client
+                    .delete_object()
+                    .bucket(bucket)
+                    .key(self.s3_key(&format!("{}.json", name)))
+                    .send()
+                    .await?;
+                for key in self
+                    .list_objects(&self.s3_key(&format!("{}/", name)), None)
+                    .await?
+                {
+                    client
+                        .delete_object()
+                        .bucket(bucket)
+                        .key(key)
+                        .send()
+                        .await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn s3_key(&self, name: &str) -> String {
+        match self {
+            BackupTarget::S3 { path, .. } if !path.is_empty() && !path.ends_with('/') => {
+                format!("{}/{}", path, name)
+            }
+            BackupTarget::S3 { path, .. } => format!("{}{}", path, name),
+            BackupTarget::Directory { .. } => unreachable!("not an S3 target"),
+        }
+    }
+
+    async fn s3_client(&self) -> Client {
+        let region = RegionProviderChain::default_provider().or_else(Region::new("us-east-1"));
+        let config = aws_config::from_env().region(region).load().await;
+        match self {
+            BackupTarget::S3 {
+                endpoint: Some(endpoint),
+                ..
+            } => Client::from_conf(
+                aws_sdk_s3::config::Builder::from(&config)
+                    .endpoint_resolver(Endpoint::immutable(endpoint.clone()))
+                    .build(),
+            ),
+            _ => Client::new(&config),
+        }
+    }
+
+    /// Lists the keys beginning with `prefix`. If `delimiter` is given, keys
+    /// containing it after the prefix are omitted.
+    async fn list_objects(
+        &self,
+        prefix: &str,
+        delimiter: Option<&str>,
+    ) -> anyhow::Result<Vec<String>> {
+        let bucket = match self {
+            BackupTarget::S3 { bucket, .. } => bucket,
+            BackupTarget::Directory { .. } => unreachable!("not an S3 target"),
+        };
+        let client = self.s3_client().await;
+        let mut keys = Vec::new();
+        let mut continuation_token = None;
+        loop {
+            let response = client
+                .list_objects_v2()
+                .bucket(bucket)
+                .prefix(prefix)
+                .set_delimiter(delimiter.map(String::from))
+                .set_continuation_token(continuation_token)
+                .send()
+                .await?;
+            keys.extend(
+                response
+                    .contents()
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|object| object.key().map(String::from)),
+            );
+            match response.next_continuation_token() {
+                Some(token) if response.is_truncated() => {
+                    continuation_token = Some(token.to_string())
+                }
+                _ => break,
+            }
+        }
+        Ok(keys)
+    }
+
+    /// Copies the backup at `directory` into the snapshot `name`, followed
+    /// by its manifest.
+    async fn upload(
+        &self,
+        name: &str,
+        directory: &Path,
+        manifest: &SnapshotManifest,
+    ) -> anyhow::Result<()> {
+        let bucket = match self {
+            BackupTarget::S3 { bucket, .. } => bucket,
+            BackupTarget::Directory { .. } => unreachable!("not an S3 target"),
+        };
+        let client = self.s3_client().await;
+        for file in files_in(directory)? {
+            let relative = file.strip_prefix(directory)?;
+            let relative = relative
+                .iter()
+                .map(|component| component.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            client
+                .put_object()
+                .bucket(bucket)
+                .key(self.s3_key(&format!("{}/{}", name, relative)))
+                .body(ByteStream::from(std::fs::read(&file)?))
+                .send()
+                .await?;
+        }
+        client
+            .put_object()
+            .bucket(bucket)
+            .key(self.s3_key(&format!("{}.json", name)))
+            .body(ByteStream::from(serde_json::to_vec_pretty(manifest)?))
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    /// Copies the snapshot `name` into `directory`, returning its manifest.
+    async fn download(&self, name: &str, directory: &Path) -> anyhow::Result<SnapshotManifest> {
+        let bucket = match self {
+            BackupTarget::S3 { bucket, .. } => bucket,
+            BackupTarget::Directory { .. } => unreachable!("not an S3 target"),
+        };
+        let client = self.s3_client().await;
+        let read = |key: String| {
+            let client = &client;
+            async move {
+                let response = client.get_object().bucket(bucket).key(key).send().await?;
+                anyhow::Ok(response.body.collect().await?.into_bytes())
+            }
+        };
+
+        let manifest =
+            serde_json::from_slice(&read(self.s3_key(&format!("{}.json", name))).await?)?;
+        let prefix = self.s3_key(&format!("{}/", name));
+        for key in self.list_objects(&prefix, None).await? {
+            let mut file = directory.to_path_buf();
+            file.extend(key[prefix.len()..].split('/'));
+            if let Some(parent) = file.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(&file, read(key).await?)?;
+        }
+        Ok(manifest)
+    }
+}
+
+/// Returns every file beneath `directory`.
+fn files_in(directory: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut directories = vec![directory.to_path_buf()];
+    while let Some(directory) = directories.pop() {
+        for entry in std::fs::read_dir(directory)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                directories.push(entry.path());
+            } else {
+                files.push(entry.path());
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Keeps snapshots consistent across collections. The backup reads each
+/// collection separately, so writes that span several collections hold this
+/// for reading, and a snapshot holds it for writing. Snapshots wait for those
+/// writes to finish, and new writes wait for the snapshot.
+#[derive(Clone, Default, Debug)]
+pub struct SnapshotLock(Arc<RwLock<()>>);
+
+impl SnapshotLock {
+    /// Waits for any snapshot in progress, and holds off new snapshots until
+    /// the returned guard is dropped.
+    pub async fn writing(&self) -> RwLockReadGuard<'_, ()> {
+        self.0.read().await
+    }
+
+    /// Waits for writes in progress, and holds off new writes until the
+    /// returned guard is dropped.
+    async fn snapshotting(&self) -> RwLockWriteGuard<'_, ()> {
+        self.0.write().await
+    }
+}
+
+/// Returns a path beside `path` with `suffix` appended to its file name.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(suffix);
+    path.with_file_name(file_name)
+}
+
+/// Creates an empty directory beside `path` that only the current user can
+/// access, for holding decrypted copies of its documents. Any directory left
+/// behind by an earlier attempt is removed first.
+pub fn staging_directory(path: &Path, label: &str) -> anyhow::Result<PathBuf> {
+    let directory = sibling(path, &format!(".{}", label));
+    let _ = std::fs::remove_dir_all(&directory);
+    DirBuilder::new()
+        .mode(0o700)
+        .create(&directory)
+        .with_context(|| format!("creating {}", directory.display()))?;
+    Ok(directory)
+}
+
+/// Writes a snapshot of every database in `storage` to `target`, returning
+/// its name. Writes holding `snapshots` are finished first and new ones wait
+/// until the documents have been copied.
+pub async fn create_snapshot(
+    storage: &AsyncStorage,
+    target: &BackupTarget,
+    snapshots: &SnapshotLock,
+) -> anyhow::Result<String> {
+    let created_at = Utc::now();
+    let name = created_at.format("%Y%m%dT%H%M%S%.3fZ").to_string();
+    let directory = match target {
+        BackupTarget::Directory { path } => path.join(&name),
+        BackupTarget::S3 { .. } => staging_directory(storage.path(), &format!("backup-{}", name))?,
+    };
+
+    let result = async {
+        let snapshotting = snapshots.snapshotting().await;
+        storage.backup(directory.clone()).await?;
+        drop(snapshotting);
+        let manifest = SnapshotManifest::read(&directory, created_at)?;
+        match target {
+            BackupTarget::Directory { path } => std::fs::write(
+                path.join(format!("{}.json", name)),
+                serde_json::to_vec_pretty(&manifest)?,
+            )?,
+            BackupTarget::S3 { .. } => target.upload(&name, &directory, &manifest).await?,
+        }
+        anyhow::Ok(())
+    }
+    .await;
+
+    if result.is_err() || matches!(target, BackupTarget::S3 { .. }) {
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+    result.map(|_| name)
+}
+
+/// Removes the oldest snapshots so that at most `retention` remain,
+/// returning the names of the removed snapshots.
+pub async fn rotate_snapshots(
+    target: &BackupTarget,
+    retention: usize,
+) -> anyhow::Result<Vec<String>> {
+    let mut snapshots = target.list_snapshots().await?;
+    let excess = snapshots.len().saturating_sub(retention);
+    snapshots.truncate(excess);
+    for name in &snapshots {
+        target.delete_snapshot(name).await?;
+    }
+    Ok(snapshots)
+}
+
+/// Takes a snapshot of `storage` every interval, removing old snapshots
+/// after each one. The updater pauses while a snapshot is taken, using
+/// `snapshots`. A snapshot in progress when shutdown is requested is finished
+/// first.
+pub async fn back_up_periodically(
+    storage: AsyncStorage,
+    configuration: BackupConfiguration,
+    snapshots: SnapshotLock,
+    shutdown: Shutdown,
+) -> anyhow::Result<()> {
+    loop {
+        tracing::info!("Backing up the database");
+        match create_snapshot(&storage, &configuration.target, &snapshots).await {
+            Ok(name) => {
+                tracing::info!("Wrote snapshot {}", name);
+                match rotate_snapshots(&configuration.target, configuration.retention).await {
+                    Ok(removed) if !removed.is_empty() => {
+                        tracing::info!("Removed old snapshots: {}", removed.join(", "));
+                    }
+                    Ok(_) => {}
+                    Err(err) => tracing::error!("Error removing old snapshots: {}", err),
+                }
+            }
+            Err(err) => tracing::error!("Error backing up the database: {}", err),
+        }
+        if shutdown.sleep(configuration.interval).await {
+            return Ok(());
+        }
+    }
+}
+
+/// Checks that the restored `database` contains exactly the documents the
+/// snapshot's manifest lists, that each of them can be deserialized, and that
+/// every event's search entries were restored with it.
+async fn verify_restored(
+    database: &AsyncDatabase,
+    manifest: &SnapshotManifest,
+) -> anyhow::Result<()> {
+    async fn verify<C>(database: &AsyncDatabase, manifest: &SnapshotManifest) -> anyhow::Result<()>
+    where
+        C: SerializedCollection + Unpin,
+        C::PrimaryKey: Unpin,
+    {
+        let restored = C::all_async(database).await?.len();
+        let expected = manifest.documents_in::<C>();
+        if restored != expected {
+            anyhow::bail!(
+                "{} has {} documents after restoring, but the snapshot contains {}",
+                C::collection_name(),
+                restored,
+                expected
+            );
+        }
+        Ok(())
+    }
+
+    verify::<Event>(database, manifest).await?;
+    verify::<SearchEntry>(database, manifest).await?;
+    verify::<Project>(database, manifest).await?;
+    verify::<RepositoryManifest>(database, manifest).await?;
+    verify::<DailySummary>(database, manifest).await?;
+
+    let urls = SearchEntry::all_async(database)
+        .await?
+        .into_iter()
+        .map(|entry| entry.contents.url)
+        .collect::<HashSet<_>>();
+    for event in Event::all_async(database).await? {
+        for entry in search::entries_for_event(&event.contents)? {
+            if !urls.contains(&entry.url) {
+                anyhow::bail!(
+                    "event {} has no search entry for {} after restoring",
+                    event.contents.id,
+                    entry.url
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the storage at `path` with the snapshot `name` using
+/// [`replace_storage`].
+pub async fn restore_snapshot(
+    path: &Path,
+    target: &BackupTarget,
+    name: &str,
+    vault_key: &VaultKeyLocation,
+) -> anyhow::Result<Option<PathBuf>> {
+    if !target
+        .list_snapshots()
+        .await?
+        .iter()
+        .any(|snapshot| snapshot == name)
+    {
+        anyhow::bail!("no complete snapshot named {}", name);
+    }
+
+    let (directory, manifest) = match target {
+        BackupTarget::Directory { path } => (
+            path.join(name),
+            serde_json::from_slice(&std::fs::read(path.join(format!("{}.json", name)))?)?,
+        ),
+        BackupTarget::S3 { .. } => {
+            let directory = staging_directory(path, &format!("restore-{}", name))?;
+            let manifest = target.download(name, &directory).await?;
+            (directory, manifest)
+        }
+    };
+
+    let result = replace_storage(path, &directory, &manifest, name, vault_key).await;
+    if matches!(target, BackupTarget::S3 { .. }) {
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+    result
+}
+
+/// Restores the backup at `directory` into a new storage directory beside
+/// `path` and verifies it against `manifest`. Only then is the storage at
+/// `path` moved to `<path>.before-<label>` and replaced with the restored
+/// storage, whose vault key is kept in `vault_key`. Returns where the
+/// previous storage was moved to, if it existed.
+pub async fn replace_storage(
+    path: &Path,
+    directory: &Path,
+    manifest: &SnapshotManifest,
+    label: &str,
+    vault_key: &VaultKeyLocation,
+) -> anyhow::Result<Option<PathBuf>> {
+    let restoring = sibling(path, ".restoring");
+    let _ = std::fs::remove_dir_all(&restoring);
+    let result = async {
+        let storage =
+            AsyncStorage::open(database::storage_configuration(&restoring, vault_key)?).await?;
+        storage.restore(directory.to_path_buf()).await?;
+        let database = storage.database::<Projects>(DATABASE_NAME).await?;
+        verify_restored(&database, manifest).await
+    }
+    .await;
+    if let Err(err) = result {
+        let _ = std::fs::remove_dir_all(&restoring);
+        return Err(err);
+    }
+
+    let previous = if path.exists() {
+        let previous = sibling(path, &format!(".before-{}", label));
+        std::fs::rename(path, &previous)?;
+        Some(previous)
+    } else {
+        None
+    };
+    std::fs::rename(&restoring, path)?;
+    Ok(previous)
+}
+
+/// Commands for backing up local storage.
+///
+/// Snapshots contain the decrypted documents, whether they are stored in the
+/// backup directory or in S3, so they need to be protected at least as well
+/// as the vault key.
+#[derive(StructOpt, Debug)]
+pub enum BackupCommand {
+    /// Writes a snapshot to the configured location and removes snapshots
+    /// beyond the retention count.
+    Create,
+    /// Lists the complete snapshots, oldest first.
+    List,
+    /// Replaces local storage with a snapshot after restoring it into a
+    /// separate directory and verifying it. The current storage is kept
+    /// beside the restored one. Stop the server before restoring.
+    Restore {
+        /// The name of the snapshot, as shown by `backup list`.
+        snapshot: String,
+    },
+}
+
+impl BackupCommand {
+    pub async fn execute(self, path: &Path, config: &Config) -> anyhow::Result<()> {
+        let configuration = BackupConfiguration::from_config(&config.backup)?.ok_or_else(|| {
+            anyhow::anyhow!(
+                "set backup.directory or backup.s3_bucket to choose where backups are stored"
+            )
+        })?;
+        let vault_key = VaultKeyLocation::from_config(&config.vault)?;
+        match self {
+            BackupCommand::Create => {
+                let database = database::open_local(path, &vault_key).await?;
+                let name = create_snapshot(
+                    &database.storage(),
+                    &configuration.target,
+                    &SnapshotLock::default(),
+                )
+                .await?;
+                println!("Wrote snapshot {}", name);
+                for removed in
+                    rotate_snapshots(&configuration.target, configuration.retention).await?
+                {
+                    println!("Removed snapshot {}", removed);
+                }
+            }
+            BackupCommand::List => {
+                for name in configuration.target.list_snapshots().await? {
+                    println!("{}", name);
+                }
+            }
+            BackupCommand::Restore { snapshot } => {
+                match restore_snapshot(path, &configuration.target, &snapshot, &vault_key).await? {
+                    Some(previous) => println!(
+                        "Restored {}. The previous storage was moved to {}",
+                        snapshot,
+                        previous.display()
+                    ),
+                    None => println!("Restored {}", snapshot),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bonsaidb::core::schema::Collection;
+
+    use super::*;
+    use crate::{
+        config::ActivitySection,
+        projects::{self, CATALOG_DIRECTORY},
+        testing::{event, issue, TestDirectory},
+        updater,
+    };
+
+    #[tokio::test]
+    async fn snapshots_rotate_and_restore() -> anyhow::Result<()> {
+        let directory = TestDirectory::new("backup-test");
+        let path = directory.join("projects.bonsaidb");
+        let target = BackupTarget::Directory {
+            path: directory.join("backups"),
+        };
+
+        let database = database::open_local(&path, &VaultKeyLocation::Storage).await?;
+        let count = projects::reload_catalog(&database, Path::new(CATALOG_DIRECTORY)).await?;
+        let mut names = Vec::new();
+        for _ in 0..3 {
+            names.push(
+                create_snapshot(&database.storage(), &target, &SnapshotLock::default()).await?,
+            );
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(target.list_snapshots().await?, names);
+        assert_eq!(rotate_snapshots(&target, 2).await?, names[..1]);
+        assert_eq!(target.list_snapshots().await?, names[1..]);
+
+        // A snapshot that doesn't match its manifest is never swapped in.
+        let restore_to = directory.join("restored.bonsaidb");
+        let manifest_path = directory.join("backups").join(format!("{}.json", names[1]));
+        let mut manifest: SnapshotManifest =
+            serde_json::from_slice(&std::fs::read(&manifest_path)?)?;
+        assert_eq!(manifest.documents_in::<Project>(), count);
+        manifest
+            .documents
+            .insert(Project::collection_name().encoded(), count + 1);
+        std::fs::write(&manifest_path, serde_json::to_vec(&manifest)?)?;
+        assert!(
+            restore_snapshot(&restore_to, &target, &names[1], &VaultKeyLocation::Storage)
+                .await
+                .is_err()
+        );
+        assert!(!restore_to.exists());
+        assert!(!sibling(&restore_to, ".restoring").exists());
+
+        assert_eq!(
+            restore_snapshot(&restore_to, &target, &names[2], &VaultKeyLocation::Storage).await?,
+            None
+        );
+        let restored = database::open_local(&restore_to, &VaultKeyLocation::Storage).await?;
+        assert_eq!(projects::catalog(&restored).await?.len(), count);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn snapshots_wait_for_writes_and_check_search_entries() -> anyhow::Result<()> {
+        let directory = TestDirectory::new("backup-consistency-test");
+        let path = directory.join("projects.bonsaidb");
+        let target = BackupTarget::Directory {
+            path: directory.join("backups"),
+        };
+        let database = database::open_local(&path, &VaultKeyLocation::Storage).await?;
+        let snapshots = SnapshotLock::default();
+        for id in 1..=2 {
+            let event = event(
+                id,
+                "IssuesEvent",
+                "2022-03-01T10:00:00Z",
+                issue("opened", id, "Views aren't compacted"),
+            );
+            updater::insert_event(&database, event, &ActivitySection::default(), &snapshots)
+                .await?;
+        }
+
+        // A snapshot isn't taken while a write is in progress.
+        let writing = snapshots.writing().await;
+        let snapshot = tokio::spawn({
+            let storage = database.storage();
+            let target = BackupTarget::Directory {
+                path: directory.join("backups"),
+            };
+            let snapshots = snapshots.clone();
+            async move { create_snapshot(&storage, &target, &snapshots).await }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(target.list_snapshots().await?.is_empty());
+        drop(writing);
+        let consistent = snapshot.await??;
+
+        // A snapshot of an event without its search entry matches its
+        // manifest, but is never swapped in.
+        SearchEntry::all_async(&database).await?[0]
+            .delete_async(&database)
+            .await?;
+        let inconsistent = create_snapshot(&database.storage(), &target, &snapshots).await?;
+        let restore_to = directory.join("restored.bonsaidb");
+        let err = restore_snapshot(
+            &restore_to,
+            &target,
+            &inconsistent,
+            &VaultKeyLocation::Storage,
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("has no search entry"), "{}", err);
+        assert!(!restore_to.exists());
+
+        restore_snapshot(
+            &restore_to,
+            &target,
+            &consistent,
+            &VaultKeyLocation::Storage,
+        )
+        .await?;
+        let restored = database::open_local(&restore_to, &VaultKeyLocation::Storage).await?;
+        assert_eq!(SearchEntry::all_async(&restored).await?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn staging_directories_are_private() -> anyhow::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let directory = TestDirectory::new("backup-staging-test");
+        let path = directory.join("projects.bonsaidb");
+        let staging = staging_directory(&path, "restore-test")?;
+        std::fs::write(staging.join("leftover"), b"documents")?;
+
+        let staging = staging_directory(&path, "restore-test")?;
+        assert_eq!(staging, directory.join("projects.bonsaidb.restore-test"));
+        assert_eq!(std::fs::read_dir(&staging)?.count(), 0);
+        assert_eq!(
+            std::fs::metadata(&staging)?.permissions().mode() & 0o777,
+            0o700
+        );
+        Ok(())
+    }
+}
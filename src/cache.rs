@@ -0,0 +1,397 @@
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap},
+    future::Future,
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    body::Bytes,
+    response::{IntoResponse, Response},
+};
+use bonsaidb::core::pubsub::{AsyncPubSub, AsyncSubscriber};
+use chrono::{DateTime, NaiveDate, Utc};
+use http::{header, HeaderMap, HeaderValue, StatusCode, Uri};
+
+use crate::backoff::Backoff;
+
+/// The PubSub topic published to after new events, projects or manifests are
+/// stored, which clears the [`PageCache`] of every running webserver.
+pub const INVALIDATE_TOPIC: &str = "page-cache-invalidate";
+
+/// A rendered response and the validators used to answer conditional
+/// requests for it.
+#[derive(Clone, Debug)]
+pub struct CachedPage {
+    content_type: &'static str,
+    body: Bytes,
+    etag: HeaderValue,
+    last_modified: Option<DateTime<Utc>>,
+}
+
+impl CachedPage {
+    /// Returns a page whose strong entity tag is derived from `body`.
+    pub fn new(
+        content_type: &'static str,
+        body: String,
+        last_modified: Option<DateTime<Utc>>,
+    ) -> Self {
+        let mut hasher = DefaultHasher::new();
+        body.hash(&mut hasher);
+        let etag = HeaderValue::from_str(&format!("\"{:016x}\"", hasher.finish())).unwrap();
+        Self {
+            content_type,
+            body: Bytes::from(body),
+            etag,
+            last_modified,
+        }
+    }
+
+    pub fn html(body: String) -> Self {
+        Self::new("text/html; charset=utf-8", body, None)
+    }
+
+    /// Returns the page, or 304 Not Modified if the request's
+    /// `If-None-Match` or `If-Modified-Since` header shows that the client
+    /// already has it.
+    pub fn respond(&self, request_headers: &HeaderMap) -> Response {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ETAG, self.etag.clone());
+        if let Some(last_modified) = self.last_modified {
+            headers.insert(
+                header::LAST_MODIFIED,
+                HeaderValue::from_str(&http_date(last_modified)).unwrap(),
+            );
+        }
+
+        if self.is_not_modified(request_headers) {
+            return (StatusCode::NOT_MODIFIED, headers).into_response();
+        }
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(self.content_type),
+        );
+        (StatusCode::OK, headers, self.body.clone()).into_response()
+    }
+
+    /// Evaluates the request's conditional headers. `If-None-Match` takes
+    /// precedence over `If-Modified-Since`, as RFC 7232 requires.
+    fn is_not_modified(&self, request_headers: &HeaderMap) -> bool {
+        if let Some(if_none_match) = request_headers.get(header::IF_NONE_MATCH) {
+            let etag = self.etag.as_bytes();
+            return if_none_match.to_str().is_ok_and(|tags| {
+                tags.split(',')
+                    .map(|tag| tag.trim())
+                    .any(|tag| tag == "*" || tag.trim_start_matches("W/").as_bytes() == etag)
+            });
+        }
+
+        match (
+            self.last_modified,
+            request_headers
+                .get(header::IF_MODIFIED_SINCE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| DateTime::parse_from_rfc2822(value).ok()),
+        ) {
+            (Some(last_modified), Some(since)) => last_modified.timestamp() <= since.timestamp(),
+            _ => false,
+        }
+    }
+}
+
+/// Formats `time` as an HTTP date, such as `Sun, 06 Nov 1994 08:49:37 GMT`.
+fn http_date(time: DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Rendered pages, keyed by their path and query string. Once the cache is
+/// full, the least recently used page makes room for the next one.
+///
+/// The cache is emptied when [`INVALIDATE_TOPIC`] is published, and when the
+/// UTC date changes, because pages without an explicit range of dates show
+/// the days leading up to today.
+#[derive(Clone, Debug)]
+pub struct PageCache {
+    max_entries: usize,
+    state: Arc<Mutex<CacheState>>,
+}
+
+#[derive(Debug)]
+struct CacheState {
+    date: NaiveDate,
+    /// Incremented each time the cache is cleared, so that pages rendered
+    /// from data older than the last invalidation aren't stored.
+    generation: u64,
+    pages: HashMap<String, CachedEntry>,
+    /// The keys of `pages` ordered by when they were last used.
+    recently_used: BTreeMap<u64, String>,
+    /// Incremented each time a page is used.
+    uses: u64,
+}
+
+#[derive(Debug)]
+struct CachedEntry {
+    page: CachedPage,
+    last_used: u64,
+}
+
+impl CacheState {
+    fn clear(&mut self) {
+        self.pages.clear();
+        self.recently_used.clear();
+        self.generation += 1;
+    }
+
+    /// Returns the page stored for `key`, marking it as the most recently
+    /// used.
+    fn use_page(&mut self, key: &str) -> Option<CachedPage> {
+        let entry = self.pages.get_mut(key)?;
+        self.recently_used.remove(&entry.last_used);
+        self.uses += 1;
+        entry.last_used = self.uses;
+        self.recently_used.insert(self.uses, key.to_string());
+        Some(entry.page.clone())
+    }
+
+    fn clear_if_outdated(&mut self, today: NaiveDate) {
+        if self.date != today {
+            self.date = today;
+            self.clear();
+        }
+    }
+}
+
+impl PageCache {
+    /// Returns a cache holding at most `max_entries` pages. If `max_entries`
+    /// is zero, nothing is cached.
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries,
+            state: Arc::new(Mutex::new(CacheState {
+                date: Utc::today().naive_utc(),
+                generation: 0,
+                pages: HashMap::new(),
+                recently_used: BTreeMap::new(),
+                uses: 0,
+            })),
+        }
+    }
+
+    pub fn clear(&self) {
+        self.state.lock().unwrap().clear();
+    }
+
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.state.lock().unwrap().pages.is_empty()
+    }
+
+    /// Returns the cached page for `key` and the cache's current generation.
+    fn get(&self, key: &str, today: NaiveDate) -> (Option<CachedPage>, u64) {
+        let mut state = self.state.lock().unwrap();
+        state.clear_if_outdated(today);
+        (state.use_page(key), state.generation)
+    }
+
+    /// Stores `page` unless the cache has been cleared since `generation`.
+    fn insert(&self, key: String, page: CachedPage, generation: u64, today: NaiveDate) {
+        if self.max_entries == 0 {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.clear_if_outdated(today);
+        if state.generation != generation {
+            return;
+        }
+        if let Some(replaced) = state.pages.remove(&key) {
+            state.recently_used.remove(&replaced.last_used);
+        } else if state.pages.len() >= self.max_entries {
+            // Every query string is a separate entry, so rather than growing
+            // without bound, drop the page that went unused the longest.
+            if let Some((_, oldest)) = state.recently_used.pop_first() {
+                state.pages.remove(&oldest);
+            }
+        }
+        state.uses += 1;
+        let last_used = state.uses;
+        state.recently_used.insert(last_used, key.clone());
+        state.pages.insert(key, CachedEntry { page, last_used });
+    }
+
+    /// Responds to a request for `uri` with its cached page, rendering and
+    /// storing the page first if it isn't cached. Errors aren't cached.
+    pub async fn respond<F>(
+        &self,
+        uri: &Uri,
+        request_headers: &HeaderMap,
+        render: F,
+    ) -> Result<Response, (StatusCode, String)>
+    where
+        F: Future<Output = Result<CachedPage, (StatusCode, String)>>,
+    {
+        let key = uri
+            .path_and_query()
+            .map_or_else(|| uri.path().to_string(), |path| path.to_string());
+        let today = Utc::today().naive_utc();
+        let (page, generation) = self.get(&key, today);
+        if let Some(page) = page {
+            return Ok(page.respond(request_headers));
+        }
+
+        let page = render.await?;
+        let response = page.respond(request_headers);
+        self.insert(key, page, generation, today);
+        Ok(response)
+    }
+}
+
+/// Tells every running webserver that the stored data changed.
+pub async fn invalidate<C: AsyncPubSub>(database: &C) -> anyhow::Result<()> {
+    database.publish(&INVALIDATE_TOPIC, &()).await?;
+    Ok(())
+}
+
+/// Clears `cache` each time [`INVALIDATE_TOPIC`] is published. If the
+/// subscription fails, it is retried until it succeeds, and the cache is
+/// cleared once it does, because invalidations may have been missed.
+pub async fn clear_when_invalidated<C: AsyncPubSub>(database: C, cache: PageCache) {
+    let mut backoff = Backoff::default();
+    loop {
+        if let Err(err) = receive_invalidations(&database, &cache, &mut backoff).await {
+            tracing::error!("Error receiving page cache invalidations: {}", err);
+        }
+        backoff.wait().await;
+    }
+}
+
+async fn receive_invalidations<C: AsyncPubSub>(
+    database: &C,
+    cache: &PageCache,
+    backoff: &mut Backoff,
+) -> anyhow::Result<()> {
+    let subscriber = database.create_subscriber().await?;
+    subscriber.subscribe_to(&INVALIDATE_TOPIC).await?;
+    backoff.reset();
+    cache.clear();
+    loop {
+        subscriber.receiver().receive_async().await?;
+        tracing::debug!("Clearing the page cache");
+        cache.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::HttpBody;
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn conditional_requests() {
+        let modified = Utc.ymd(2022, 3, 3).and_hms(10, 0, 0);
+        let page = CachedPage::new("application/feed+json", String::from("{}"), Some(modified));
+        let response = page.respond(&HeaderMap::new());
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()[header::ETAG].clone();
+        assert_eq!(
+            response.headers()[header::LAST_MODIFIED],
+            "Thu, 03 Mar 2022 10:00:00 GMT"
+        );
+
+        let request = |name, value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(name, HeaderValue::from_str(value).unwrap());
+            page.respond(&headers).status()
+        };
+        let etag = etag.to_str().unwrap();
+        assert_eq!(
+            request(header::IF_NONE_MATCH, etag),
+            StatusCode::NOT_MODIFIED
+        );
+        assert_eq!(
+            request(header::IF_NONE_MATCH, &format!("\"other\", W/{}", etag)),
+            StatusCode::NOT_MODIFIED
+        );
+        assert_eq!(request(header::IF_NONE_MATCH, "\"other\""), StatusCode::OK);
+        assert_eq!(
+            request(header::IF_MODIFIED_SINCE, "Thu, 03 Mar 2022 10:00:00 GMT"),
+            StatusCode::NOT_MODIFIED
+        );
+        assert_eq!(
+            request(header::IF_MODIFIED_SINCE, "Thu, 03 Mar 2022 09:59:59 GMT"),
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn pages_are_rendered_once_until_cleared() {
+        let cache = PageCache::new(2);
+        let uri = Uri::from_static("/?from=2022-03-01&to=2022-03-05");
+        let render = |body: &'static str| async move { Ok(CachedPage::html(String::from(body))) };
+        let body =
+            |response: Response| async move { response.into_body().data().await.unwrap().unwrap() };
+
+        let first = cache
+            .respond(&uri, &HeaderMap::new(), render("first"))
+            .await;
+        assert_eq!(body(first.unwrap()).await, "first");
+        let cached = cache
+            .respond(&uri, &HeaderMap::new(), render("second"))
+            .await;
+        assert_eq!(body(cached.unwrap()).await, "first");
+
+        let failed = cache
+            .respond(&Uri::from_static("/missing"), &HeaderMap::new(), async {
+                Err((StatusCode::NOT_FOUND, String::from("missing")))
+            })
+            .await;
+        assert_eq!(failed.unwrap_err().0, StatusCode::NOT_FOUND);
+
+        cache.clear();
+        let rendered = cache
+            .respond(&uri, &HeaderMap::new(), render("second"))
+            .await;
+        assert_eq!(body(rendered.unwrap()).await, "second");
+
+        // A page rendered before the cache was cleared isn't stored.
+        let today = Utc::today().naive_utc();
+        let (_, generation) = cache.get("/stale", today);
+        cache.clear();
+        cache.insert(
+            String::from("/stale"),
+            CachedPage::html(String::from("stale")),
+            generation,
+            today,
+        );
+        assert!(cache.get("/stale", today).0.is_none());
+    }
+
+    #[test]
+    fn least_recently_used_pages_are_evicted() {
+        let cache = PageCache::new(2);
+        let today = Utc::today().naive_utc();
+        let insert = |key: &str| {
+            let (_, generation) = cache.get(key, today);
+            cache.insert(
+                key.to_string(),
+                CachedPage::html(key.to_string()),
+                generation,
+                today,
+            );
+        };
+
+        insert("/a");
+        insert("/b");
+        assert!(cache.get("/a", today).0.is_some());
+        insert("/c");
+        assert!(cache.get("/b", today).0.is_none());
+        assert!(cache.get("/a", today).0.is_some());
+        assert!(cache.get("/c", today).0.is_some());
+
+        // Replacing a page doesn't evict another.
+        insert("/c");
+        assert!(cache.get("/a", today).0.is_some());
+    }
+}
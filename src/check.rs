@@ -0,0 +1,482 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::{self, Display},
+    path::Path,
+};
+
+use bonsaidb::core::connection::AsyncConnection;
+use chrono::{Duration, Utc};
+
+use crate::{
+    config::ActivitySection,
+    html,
+    projects::{last_activity, read_project_files, split_project_file, Project, Relationship},
+};
+
+/// How many days back to look for repositories with activity.
+const ACTIVE_DAYS: i64 = 90;
+
+/// Elements that never have an end tag.
+const VOID_ELEMENTS: [&str; 6] = ["br", "hr", "img", "input", "meta", "wbr"];
+/// Elements whose end tag may be omitted.
+const OPTIONAL_END_ELEMENTS: [&str; 2] = ["p", "li"];
+
+/// A problem found in a project file.
+#[derive(Debug)]
+pub struct Problem {
+    /// The file name within the catalog directory.
+    pub file: String,
+    pub message: String,
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.file, self.message)
+    }
+}
+
+/// Checks every project file in `directory`, returning the problems found.
+pub fn check_files(directory: &Path) -> anyhow::Result<Vec<Problem>> {
+    let files = read_project_files(directory)?;
+    let mut problems = Vec::new();
+    let mut projects = Vec::new();
+    let mut files_by_key = HashMap::<&str, Vec<String>>::new();
+    for file in &files {
+        let file_name = file
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        files_by_key
+            .entry(&file.key)
+            .or_default()
+            .push(file_name.clone());
+        match &file.project {
+            Ok(project) => projects.push((file_name, file.key.as_str(), &file.contents, project)),
+            Err(err) => problems.push(Problem {
+                file: file_name,
+                message: err.to_string(),
+            }),
+        }
+    }
+
+    for (key, file_names) in &files_by_key {
+        if file_names.len() > 1 {
+            for file in file_names {
+                problems.push(Problem {
+                    file: file.clone(),
+                    message: format!(
+                        "duplicate project key `{}` also used by {}",
+                        key,
+                        file_names
+                            .iter()
+                            .filter(|other| *other != file)
+                            .cloned()
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                });
+            }
+        }
+    }
+
+    let mut repositories = HashMap::<String, &str>::new();
+    for (file, key, contents, project) in &projects {
+        let mut problem = |message: String| {
+            problems.push(Problem {
+                file: file.clone(),
+                message,
+            })
+        };
+        for message in check_project(key, contents, project, &files_by_key) {
+            problem(message);
+        }
+        if let Some(other) = repositories.insert(project.repository.to_lowercase(), key) {
+            problem(format!(
+                "repository {} is also used by `{}`",
+                project.repository, other
+            ));
+        }
+    }
+
+    problems.sort_by(|a, b| a.file.cmp(&b.file));
+    Ok(problems)
+}
+
+/// Checks a single project, read from `contents`, against its key and the
+/// keys of the other projects in the catalog.
+fn check_project(
+    key: &str,
+    contents: &str,
+    project: &Project,
+    keys: &HashMap<&str, Vec<String>>,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+    if project.name.to_lowercase() != key {
+        problems.push(format!(
+            "name `{}` doesn't match the file name `{}`",
+            project.name, key
+        ));
+    }
+
+    match project.repository_name() {
+        Some(repository) => {
+            let repository_key = repository.rsplit('/').next().unwrap_or_default();
+            if repository_key.to_lowercase() != key {
+                problems.push(format!(
+                    "repository {} doesn't match the project `{}`",
+                    project.repository, key
+                ));
+            }
+        }
+        None => problems.push(format!(
+            "repository {} isn't a GitHub repository URL",
+            project.repository
+        )),
+    }
+
+    for (field, url) in [
+        ("homepage", &project.homepage),
+        ("documentation", &project.documentation),
+    ] {
+        if let Some(url) = url {
+            if !url.starts_with("https://") {
+                problems.push(format!("{} {} isn't an https URL", field, url));
+            } else if !mentions_project(url, key) {
+                problems.push(format!(
+                    "{} {} doesn't mention the project `{}`",
+                    field, url, key
+                ));
+            }
+        }
+    }
+
+    for (relationship, related) in project
+        .relationships()
+        .map(|(relationship, related)| {
+            let field = match relationship {
+                Relationship::DependsOn => "depends_on",
+                Relationship::BuiltFor => "built_for",
+                Relationship::Supersedes => "supersedes",
+            };
+            (field, related)
+        })
+        .chain(
+            project
+                .waiting_on
+                .iter()
+                .map(|related| ("waiting_on", related.as_str())),
+        )
+    {
+        if related == key {
+            problems.push(format!("{} refers to itself", relationship));
+        } else if !keys.contains_key(related) {
+            problems.push(format!(
+                "{} refers to unknown project `{}`",
+                relationship, related
+            ));
+        }
+    }
+
+    // The stored description has already been sanitized, which silently
+    // repairs broken markup, so the HTML within the Markdown is checked.
+    if let Ok((_, description)) = split_project_file(contents) {
+        if let Err(err) = check_html(&html::markdown_to_html(description)) {
+            problems.push(format!("malformed description: {}", err));
+        }
+    }
+
+    problems
+}
+
+/// Returns true if `url` contains `key`, ignoring case and treating `-` and
+/// `_` as the same character.
+fn mentions_project(url: &str, key: &str) -> bool {
+    let normalize = |text: &str| text.to_lowercase().replace('_', "-");
+    normalize(url).contains(&normalize(key))
+}
+
+/// Checks that the tags in `html` are well-formed: every attribute value is
+/// terminated, and every element that requires an end tag is closed in the
+/// order it was opened.
+pub fn check_html(html: &str) -> Result<(), String> {
+    let mut open = Vec::<String>::new();
+    let mut remaining = html;
+    while let Some(start) = remaining.find('<') {
+        let tag = &remaining[start + 1..];
+        let (closing, tag) = match tag.strip_prefix('/') {
+            Some(tag) => (true, tag),
+            None => (false, tag),
+        };
+        if tag.starts_with('!') {
+            let end = tag
+                .find('>')
+                .ok_or_else(|| String::from("unterminated comment"))?;
+            remaining = &tag[end + 1..];
+            continue;
+        }
+
+        let name_length = tag
+            .find(|ch: char| !ch.is_ascii_alphanumeric())
+            .unwrap_or(tag.len());
+        if name_length == 0 {
+            return Err(format!(
+                "`<` isn't followed by a tag name near `{}`",
+                excerpt(remaining, start)
+            ));
+        }
+        let name = tag[..name_length].to_ascii_lowercase();
+        let end = tag_end(&tag[name_length..]).map_err(|err| format!("<{}>: {}", name, err))?;
+        let self_closing = tag[name_length..name_length + end]
+            .trim_end()
+            .ends_with('/');
+        remaining = &tag[name_length + end + 1..];
+
+        if VOID_ELEMENTS.contains(&name.as_str()) || OPTIONAL_END_ELEMENTS.contains(&name.as_str())
+        {
+            continue;
+        }
+        if closing {
+            match open.pop() {
+                Some(expected) if expected == name => {}
+                Some(expected) => {
+                    return Err(format!("</{}> closes <{}>", name, expected));
+                }
+                None => return Err(format!("</{}> has no opening tag", name)),
+            }
+        } else if !self_closing {
+            open.push(name);
+        }
+    }
+
+    match open.pop() {
+        Some(unclosed) => Err(format!("<{}> is never closed", unclosed)),
+        None => Ok(()),
+    }
+}
+
+/// Returns the offset of the `>` ending a tag whose attributes begin
+/// `attributes`.
+fn tag_end(attributes: &str) -> Result<usize, String> {
+    let mut chars = attributes.char_indices();
+    while let Some((index, ch)) = chars.next() {
+        match ch {
+            '>' => return Ok(index),
+            '<' => return Err(String::from("tag is missing its closing `>`")),
+            '"' | '\'' => {
+                let value_start = index + 1;
+                let value_end = attributes[value_start..]
+                    .find(ch)
+                    .ok_or_else(|| String::from("unterminated attribute value"))?;
+                let value = &attributes[value_start..value_start + value_end];
+                if value.contains(['<', '>']) {
+                    return Err(format!(
+                        "unterminated attribute value `{}`",
+                        value.split(['<', '>']).next().unwrap_or_default()
+                    ));
+                }
+                for _ in value.chars().chain(Some(ch)) {
+                    chars.next();
+                }
+            }
+            _ => {}
+        }
+    }
+    Err(String::from("tag is missing its closing `>`"))
+}
+
+fn excerpt(text: &str, start: usize) -> &str {
+    let text = &text[start..];
+    let end = text
+        .char_indices()
+        .nth(20)
+        .map_or(text.len(), |(index, _)| index);
+    &text[..end]
+}
+
+/// Returns the repositories with events in the last 90 days that aren't in
+/// the catalog, excluding forks of other projects.
+pub async fn missing_repositories<C: AsyncConnection>(
+    database: &C,
+    directory: &Path,
+    activity: &ActivitySection,
+) -> anyhow::Result<BTreeSet<String>> {
+    let keys = read_project_files(directory)?
+        .into_iter()
+        .map(|file| file.key)
+        .collect::<BTreeSet<_>>();
+    let since = Utc::now() - Duration::days(ACTIVE_DAYS);
+    Ok(last_activity(database)
+        .await?
+        .into_iter()
+        .filter(|(_, latest)| *latest >= since)
+        .map(|(repository, _)| repository)
+        .filter(|repository| {
+            let name = repository.rsplit('/').next().unwrap_or_default();
+            !keys.contains(&name.to_lowercase()) && activity.forked_repository(name).is_none()
+        })
+        .collect())
+}
+
+/// Checks the catalog in `directory` and prints the problems found, along
+/// with the repositories that are missing from the catalog. Returns an error
+/// if any project file has a problem.
+pub async fn check_projects<C: AsyncConnection>(
+    database: &C,
+    directory: &Path,
+    activity: &ActivitySection,
+) -> anyhow::Result<()> {
+    let problems = check_files(directory)?;
+    for problem in &problems {
+        println!("error: {}", problem);
+    }
+    for repository in missing_repositories(database, directory, activity).await? {
+        println!(
+            "warning: {} has recent activity but no project file",
+            repository
+        );
+    }
+
+    if problems.is_empty() {
+        println!("The project catalog is consistent.");
+        Ok(())
+    } else {
+        anyhow::bail!("found {} problems in the project catalog", problems.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backup::SnapshotLock,
+        database::{self, VaultKeyLocation},
+        projects::CATALOG_DIRECTORY,
+        testing::{event, issue, TestDirectory},
+        updater,
+    };
+
+    fn write_catalog(name: &str, files: &[(&str, &str)]) -> TestDirectory {
+        let directory = TestDirectory::new(&format!("check-{}", name));
+        for (file, contents) in files {
+            std::fs::write(directory.join(file), contents).unwrap();
+        }
+        directory
+    }
+
+    #[test]
+    fn catalog_is_consistent() {
+        let problems = check_files(Path::new(CATALOG_DIRECTORY)).unwrap();
+        assert!(
+            problems.is_empty(),
+            "{}",
+            problems
+                .iter()
+                .map(Problem::to_string)
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+    }
+
+    #[test]
+    fn mismatched_urls() {
+        let directory = write_catalog(
+            "urls",
+            &[(
+                "kludgine.md",
+                "+++\nname = \"Kludgine\"\ntagline = \"\"\nrepository = \"https://github.com/khonsulabs/nebari\"\ndocumentation = \"https://nebari.bonsaidb.io/main/nebari\"\n+++\n",
+            )],
+        );
+        let problems = check_files(&directory).unwrap();
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems[0].message.starts_with("repository"));
+        assert!(problems[1].message.starts_with("documentation"));
+    }
+
+    #[test]
+    fn duplicates_and_unknown_keys() {
+        let project = |name: &str, extra: &str| {
+            format!(
+                "+++\nname = \"{}\"\ntagline = \"\"\nrepository = \"https://github.com/khonsulabs/{}\"\n{}\n+++\n",
+                name, name.to_lowercase(), extra
+            )
+        };
+        let directory = write_catalog(
+            "duplicates",
+            &[
+                ("pot.md", &project("pot", "depends_on = [\"serde\"]")),
+                ("Pot.md", &project("Pot", "waiting_on = \"pot\"")),
+            ],
+        );
+        let problems = check_files(&directory).unwrap();
+        let messages = problems
+            .iter()
+            .map(|problem| problem.message.as_str())
+            .collect::<Vec<_>>();
+        assert!(messages
+            .iter()
+            .any(|m| m.starts_with("duplicate project key")));
+        assert!(messages
+            .iter()
+            .any(|m| m.starts_with("repository https://github.com/khonsulabs/pot is also used")));
+        assert!(messages.contains(&"depends_on refers to unknown project `serde`"));
+        assert!(messages.contains(&"waiting_on refers to itself"));
+    }
+
+    #[test]
+    fn html() {
+        assert!(
+            check_html("<p>One<p>Two <a href=\"https://khonsulabs.com\">link</a>.<br>").is_ok()
+        );
+        assert!(check_html("<ul><li>One<li>Two</ul>").is_ok());
+        assert_eq!(
+            check_html("<a href=\"https://github.com/cloudhead/rgx>rgx</a> aimed at").unwrap_err(),
+            "<a>: unterminated attribute value"
+        );
+        assert_eq!(
+            check_html("<a href=\"https://github.com/cloudhead/rgx>rgx</a> \"quoted\"")
+                .unwrap_err(),
+            "<a>: unterminated attribute value `https://github.com/cloudhead/rgx`"
+        );
+        assert_eq!(
+            check_html("<em>text</strong>").unwrap_err(),
+            "</strong> closes <em>"
+        );
+        assert_eq!(
+            check_html("<code>text").unwrap_err(),
+            "<code> is never closed"
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_repositories_had_recent_activity() -> anyhow::Result<()> {
+        let directory = TestDirectory::new("check-missing");
+        let database = database::open_local(
+            &directory.join("projects.bonsaidb"),
+            &VaultKeyLocation::Storage,
+        )
+        .await?;
+
+        let activity = ActivitySection::default();
+        let recent = (Utc::now() - Duration::days(1)).to_rfc3339();
+        let old = (Utc::now() - Duration::days(ACTIVE_DAYS + 30)).to_rfc3339();
+        for (id, repository, created_at) in [
+            (1, "khonsulabs/bonsaidb", recent.as_str()),
+            (2, "khonsulabs/uncataloged", old.as_str()),
+            (3, "khonsulabs/uncataloged", recent.as_str()),
+            (4, "khonsulabs/abandoned", old.as_str()),
+        ] {
+            let mut event = event(id, "IssuesEvent", created_at, issue("opened", id, "Issue"));
+            event.repository.name = String::from(repository);
+            updater::insert_event(&database, event, &activity, &SnapshotLock::default()).await?;
+        }
+
+        let missing =
+            missing_repositories(&database, Path::new(CATALOG_DIRECTORY), &activity).await?;
+        assert_eq!(
+            missing.into_iter().collect::<Vec<_>>(),
+            ["khonsulabs/uncataloged"]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,545 @@
+use std::{
+    fmt::Display,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::Context;
+use bonsaidb::client::url::Url;
+use serde::Deserialize;
+
+use crate::{
+    backup::BackupConfiguration,
+    database::{DatabaseConfiguration, VaultKeyLocation, LOCAL_STORAGE_PATH},
+};
+
+/// The file configuration is read from when `--config` isn't given. It is
+/// optional.
+pub const DEFAULT_CONFIG_PATH: &str = "khonsulabs-projects.toml";
+
+/// The application's configuration. Each setting comes from the first of
+/// these that provides it: command line flags, environment variables, the
+/// configuration file, and the defaults below.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub database: DatabaseSection,
+    pub vault: VaultSection,
+    pub backup: BackupSection,
+    pub github: GitHubSection,
+    pub server: ServerSection,
+    pub log: LogSection,
+    pub activity: ActivitySection,
+    pub shutdown: ShutdownSection,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseSection {
+    /// The directory of local storage, used unless `url` is set.
+    pub path: PathBuf,
+    /// The URL of a BonsaiDb server to connect to instead of local storage.
+    pub url: Option<String>,
+    /// The DER-encoded certificate of the server, pinned instead of being
+    /// validated against the system's roots.
+    pub certificate: Option<PathBuf>,
+}
+
+impl Default for DatabaseSection {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from(LOCAL_STORAGE_PATH),
+            url: None,
+            certificate: None,
+        }
+    }
+}
+
+/// Where the key sealing local storage's vault is kept. If neither
+/// `key_directory` nor `s3_bucket` is set, it is kept within the storage
+/// directory.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct VaultSection {
+    pub key_directory: Option<PathBuf>,
+    pub s3_bucket: Option<String>,
+    pub s3_endpoint: Option<String>,
+    pub s3_path: Option<String>,
+}
+
+/// Where snapshots of local storage are written. Scheduled backups are only
+/// taken if `directory` or `s3_bucket` is set.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackupSection {
+    pub directory: Option<PathBuf>,
+    pub s3_bucket: Option<String>,
+    pub s3_endpoint: Option<String>,
+    pub s3_path: Option<String>,
+    /// The number of snapshots to keep.
+    pub retention: usize,
+    pub interval_hours: u64,
+}
+
+impl Default for BackupSection {
+    fn default() -> Self {
+        Self {
+            directory: None,
+            s3_bucket: None,
+            s3_endpoint: None,
+            s3_path: None,
+            retention: 7,
+            interval_hours: 24,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct GitHubSection {
+    /// The organization whose events are shown.
+    pub organization: String,
+    /// The user `token` belongs to.
+    pub user: String,
+    /// A personal access token. Requests are unauthenticated without one,
+    /// which GitHub limits to 60 requests an hour.
+    pub token: Option<String>,
+    /// The seconds between requests for new events.
+    pub poll_interval_secs: u64,
+}
+
+impl Default for GitHubSection {
+    fn default() -> Self {
+        Self {
+            organization: String::from("khonsulabs"),
+            user: String::from("ecton"),
+            token: None,
+            poll_interval_secs: 300,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSection {
+    /// The address the webserver listens on.
+    pub bind: BindAddress,
+    /// The URL the site is reached at, used for links that must be absolute,
+    /// such as those in feeds.
+    pub public_url: String,
+    /// The most rendered pages kept in memory. Zero disables the cache.
+    pub page_cache_entries: usize,
+    /// The PEM-encoded certificate chain served over TLS. HTTPS is served if
+    /// this and `tls_key` are set, and both are reloaded when they change.
+    pub tls_certificate: Option<PathBuf>,
+    /// The PEM-encoded private key of `tls_certificate`.
+    pub tls_key: Option<PathBuf>,
+}
+
+impl ServerSection {
+    /// Returns the absolute URL of `path` on the site.
+    pub fn public_link(&self, path: &str) -> String {
+        format!("{}{}", self.public_url.trim_end_matches('/'), path)
+    }
+}
+
+impl Default for ServerSection {
+    fn default() -> Self {
+        Self {
+            bind: BindAddress::Tcp(SocketAddr::from(([127, 0, 0, 1], 3000))),
+            public_url: String::from("https://khonsulabs.com"),
+            page_cache_entries: 256,
+            tls_certificate: None,
+            tls_key: None,
+        }
+    }
+}
+
+/// Where the webserver listens: a TCP address such as `127.0.0.1:3000`, or a
+/// Unix domain socket written as `unix:/run/khonsulabs-projects.sock`.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum BindAddress {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for BindAddress {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("unix:") {
+            Some("") => anyhow::bail!("unix socket path must not be empty"),
+            Some(path) => Ok(Self::Unix(PathBuf::from(path))),
+            None => s
+                .parse()
+                .map(Self::Tcp)
+                .map_err(|_| anyhow::anyhow!("invalid socket address {:?}", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for BindAddress {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl Display for BindAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BindAddress::Tcp(address) => address.fmt(f),
+            BindAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogSection {
+    pub format: LogFormat,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Pretty,
+    Compact,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pretty" => Ok(Self::Pretty),
+            "compact" => Ok(Self::Compact),
+            "json" => Ok(Self::Json),
+            other => anyhow::bail!("unknown log format {:?}", other),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownSection {
+    /// The seconds to wait after SIGINT or SIGTERM for requests and the
+    /// updater's current batch to finish before exiting anyway.
+    pub timeout_secs: u64,
+}
+
+impl Default for ShutdownSection {
+    fn default() -> Self {
+        Self { timeout_secs: 30 }
+    }
+}
+
+/// Which activity is shown from repositories outside of the organization.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ActivitySection {
+    /// Repositories we maintain forks of, listed by their upstream names.
+    pub forked_repositories: Vec<String>,
+    /// Commit authors whose commits to forked repositories are shown.
+    pub contributor_emails: Vec<String>,
+}
+
+impl Default for ActivitySection {
+    fn default() -> Self {
+        Self {
+            forked_repositories: [
+                "iqlusioninc/crates",
+                "novifinancial/opaque-ke",
+                "dalek-cryptography/curve25519-dalek",
+                "RustCrypto/password-hashes",
+                "novifinancial/voprf",
+                "ModProg/derive-where",
+                "ModProg/derive-restricted",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+            contributor_emails: ["jon@khonsulabs.com", "daxpedda@gmail.com"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        }
+    }
+}
+
+impl ActivitySection {
+    /// Returns the upstream name of the repository we forked as
+    /// `repository_name`, if it is a fork.
+    pub fn forked_repository(&self, repository_name: &str) -> Option<&str> {
+        self.forked_repositories
+            .iter()
+            .map(String::as_str)
+            .find(|repo| repo.split('/').nth(1) == Some(repository_name))
+    }
+}
+
+impl Config {
+    /// Reads the configuration file at `path`, or [`DEFAULT_CONFIG_PATH`] if
+    /// it exists and `path` is `None`, and then applies the overrides from
+    /// the environment.
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Self> {
+        let path = match path {
+            Some(path) => Some(path),
+            None => Some(Path::new(DEFAULT_CONFIG_PATH)).filter(|path| path.exists()),
+        };
+        let mut config = match path {
+            Some(path) => {
+                let contents = std::fs::read_to_string(path)
+                    .with_context(|| format!("error reading {}", path.display()))?;
+                Self::parse(&contents).with_context(|| format!("error in {}", path.display()))?
+            }
+            None => Self::default(),
+        };
+        config.apply_overrides(|name| std::env::var(name).ok())?;
+        Ok(config)
+    }
+
+    pub fn parse(contents: &str) -> anyhow::Result<Self> {
+        Ok(toml::from_str(contents)?)
+    }
+
+    /// Replaces settings with the values `var` returns for their environment
+    /// variables.
+    pub fn apply_overrides(&mut self, var: impl Fn(&str) -> Option<String>) -> anyhow::Result<()> {
+        let var = &var;
+        set(&mut self.database.path, "BONSAIDB_PATH", var)?;
+        set_option(&mut self.database.url, "BONSAIDB_URL", var)?;
+        set_option(&mut self.database.certificate, "BONSAIDB_CERTIFICATE", var)?;
+
+        set_option(&mut self.vault.key_directory, "VAULT_KEY_DIRECTORY", var)?;
+        set_option(&mut self.vault.s3_bucket, "VAULT_S3_BUCKET", var)?;
+        set_option(&mut self.vault.s3_endpoint, "VAULT_S3_ENDPOINT", var)?;
+        set_option(&mut self.vault.s3_path, "VAULT_S3_PATH", var)?;
+
+        set_option(&mut self.backup.directory, "BACKUP_DIRECTORY", var)?;
+        set_option(&mut self.backup.s3_bucket, "BACKUP_S3_BUCKET", var)?;
+        set_option(&mut self.backup.s3_endpoint, "BACKUP_S3_ENDPOINT", var)?;
+        set_option(&mut self.backup.s3_path, "BACKUP_S3_PATH", var)?;
+        set(&mut self.backup.retention, "BACKUP_RETENTION", var)?;
+        set(
+            &mut self.backup.interval_hours,
+            "BACKUP_INTERVAL_HOURS",
+            var,
+        )?;
+
+        set(&mut self.github.organization, "GITHUB_ORGANIZATION", var)?;
+        set(&mut self.github.user, "GITHUB_USER", var)?;
+        set_option(&mut self.github.token, "GITHUB_TOKEN", var)?;
+        set(
+            &mut self.github.poll_interval_secs,
+            "GITHUB_POLL_INTERVAL_SECS",
+            var,
+        )?;
+
+        set(&mut self.server.bind, "BIND_ADDRESS", var)?;
+        set(&mut self.server.public_url, "PUBLIC_URL", var)?;
+        set(
+            &mut self.server.page_cache_entries,
+            "SERVER_PAGE_CACHE_ENTRIES",
+            var,
+        )?;
+        set_option(
+            &mut self.server.tls_certificate,
+            "SERVER_TLS_CERTIFICATE",
+            var,
+        )?;
+        set_option(&mut self.server.tls_key, "SERVER_TLS_KEY", var)?;
+        set(&mut self.log.format, "LOG_FORMAT", var)?;
+
+        set(
+            &mut self.shutdown.timeout_secs,
+            "SHUTDOWN_TIMEOUT_SECS",
+            var,
+        )?;
+
+        if let Some(repositories) = var("FORKED_REPOSITORIES") {
+            self.activity.forked_repositories = split_list(&repositories);
+        }
+        if let Some(emails) = var("CONTRIBUTOR_EMAILS") {
+            self.activity.contributor_emails = split_list(&emails);
+        }
+        Ok(())
+    }
+
+    /// Checks the settings that depend on each other or can't be checked
+    /// while parsing, returning every problem at once.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut problems = Vec::new();
+        if let Err(err) = DatabaseConfiguration::from_config(&self.database) {
+            problems.push(format!("database: {:#}", err));
+        }
+        if let Err(err) = VaultKeyLocation::from_config(&self.vault) {
+            problems.push(format!("vault: {:#}", err));
+        }
+        if let Err(err) = BackupConfiguration::from_config(&self.backup) {
+            problems.push(format!("backup: {:#}", err));
+        }
+        if self.github.organization.is_empty() {
+            problems.push(String::from("github: organization must not be empty"));
+        }
+        if self.github.poll_interval_secs == 0 {
+            problems.push(String::from(
+                "github: poll_interval_secs must be at least 1",
+            ));
+        }
+        if !matches!(Url::parse(&self.server.public_url), Ok(url) if url.has_host()) {
+            problems.push(format!(
+                "server: public_url {:?} must be an absolute URL",
+                self.server.public_url
+            ));
+        }
+        if self.server.tls_certificate.is_some() != self.server.tls_key.is_some() {
+            problems.push(String::from(
+                "server: tls_certificate and tls_key must be set together",
+            ));
+        }
+        for repository in &self.activity.forked_repositories {
+            if repository.split('/').count() != 2 {
+                problems.push(format!(
+                    "activity: forked repository {:?} must be written as owner/name",
+                    repository
+                ));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            anyhow::bail!("invalid configuration:\n  {}", problems.join("\n  "))
+        }
+    }
+}
+
+fn set<T>(field: &mut T, name: &str, var: &impl Fn(&str) -> Option<String>) -> anyhow::Result<()>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Some(value) = var(name) {
+        *field = value
+            .parse()
+            .map_err(|err| anyhow::anyhow!("invalid {}: {}", name, err))?;
+    }
+    Ok(())
+}
+
+fn set_option<T>(
+    field: &mut Option<T>,
+    name: &str,
+    var: &impl Fn(&str) -> Option<String>,
+) -> anyhow::Result<()>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Some(value) = var(name) {
+        *field = if value.is_empty() {
+            None
+        } else {
+            Some(
+                value
+                    .parse()
+                    .map_err(|err| anyhow::anyhow!("invalid {}: {}", name, err))?,
+            )
+        };
+    }
+    Ok(())
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn environment_overrides_file() {
+        let mut config = Config::parse(
+            "[github]\norganization = \"bonsaidb\"\ntoken = \"from-file\"\n\n[backup]\ndirectory = \"backups\"\nretention = 3\n",
+        )
+        .unwrap();
+        let env = HashMap::from([("GITHUB_TOKEN", "from-env"), ("BACKUP_RETENTION", "5")]);
+        config
+            .apply_overrides(|name| env.get(name).map(|value| value.to_string()))
+            .unwrap();
+
+        assert_eq!(config.github.organization, "bonsaidb");
+        assert_eq!(config.github.token.as_deref(), Some("from-env"));
+        assert_eq!(config.github.user, "ecton");
+        assert_eq!(config.backup.directory, Some(PathBuf::from("backups")));
+        assert_eq!(config.backup.retention, 5);
+        assert_eq!(config.log.format, LogFormat::Pretty);
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn readable_errors() {
+        let err = Config::parse("[server]\nbind = \"localhost\"\n").unwrap_err();
+        assert!(
+            err.to_string().contains("invalid socket address"),
+            "{}",
+            err
+        );
+        let err = Config::parse("[github]\ntokn = \"abc\"\n").unwrap_err();
+        assert!(err.to_string().contains("unknown field `tokn`"), "{}", err);
+
+        let mut config = Config::default();
+        let err = config
+            .apply_overrides(|name| (name == "BACKUP_RETENTION").then(|| String::from("many")))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid BACKUP_RETENTION: invalid digit found in string"
+        );
+
+        let config = Config::parse(
+            "[backup]\ndirectory = \"backups\"\ns3_bucket = \"projects\"\n\n[activity]\nforked_repositories = [\"opaque-ke\"]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "invalid configuration:\n  backup: only one of directory and s3_bucket may be set\n  activity: forked repository \"opaque-ke\" must be written as owner/name"
+        );
+    }
+
+    #[test]
+    fn bind_addresses() {
+        let config = Config::parse(
+            "[server]\nbind = \"unix:/run/projects.sock\"\ntls_certificate = \"cert.pem\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.server.bind,
+            BindAddress::Unix(PathBuf::from("/run/projects.sock"))
+        );
+        assert_eq!(config.server.bind.to_string(), "unix:/run/projects.sock");
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "invalid configuration:\n  server: tls_certificate and tls_key must be set together"
+        );
+
+        assert_eq!(
+            "[::1]:8080".parse::<BindAddress>().unwrap(),
+            BindAddress::Tcp(SocketAddr::from((std::net::Ipv6Addr::LOCALHOST, 8080)))
+        );
+        assert!("unix:".parse::<BindAddress>().is_err());
+    }
+}
@@ -0,0 +1,321 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use bonsaidb::{
+    client::{fabruic::Certificate, url::Url, Client, RemoteDatabase},
+    core::{connection::AsyncStorageConnection, document::KeyId},
+    keystorage::s3::{aws_sdk_s3::Endpoint, S3VaultKeyStorage},
+    local::{
+        config::{Builder, StorageConfiguration},
+        vault::LocalVaultKeyStorage,
+        AsyncDatabase, AsyncStorage,
+    },
+};
+use http::Uri;
+
+use crate::{
+    config::{DatabaseSection, VaultSection},
+    schema::Projects,
+};
+
+/// The name of the database containing the `Projects` schema.
+pub const DATABASE_NAME: &str = "projects";
+/// The path local storage is opened from by default.
+pub const LOCAL_STORAGE_PATH: &str = "projects.bonsaidb";
+
+/// Where the database is stored.
+#[derive(Debug)]
+pub enum DatabaseConfiguration {
+    /// Storage opened within this process.
+    Local { path: PathBuf },
+    /// A connection to a BonsaiDb server. The server must have the `Projects`
+    /// schema registered.
+    Server {
+        url: Url,
+        /// The path to the server's DER-encoded certificate, which is pinned
+        /// instead of validating the certificate against the system's roots.
+        certificate: Option<PathBuf>,
+    },
+}
+
+impl DatabaseConfiguration {
+    /// Connects to the server at `url` if it is set. Otherwise, local storage
+    /// at `path` is used.
+    pub fn from_config(database: &DatabaseSection) -> anyhow::Result<Self> {
+        match &database.url {
+            Some(url) => Ok(Self::Server {
+                url: Url::parse(url).with_context(|| format!("invalid url {:?}", url))?,
+                certificate: database.certificate.clone(),
+            }),
+            None => Ok(Self::Local {
+                path: database.path.clone(),
+            }),
+        }
+    }
+}
+
+/// Where the key protecting the vault of local storage is kept.
+#[derive(Debug)]
+pub enum VaultKeyLocation {
+    /// Within the storage directory, which only protects against accidental
+    /// disclosure.
+    Storage,
+    /// In a file named after the storage's id within `path`.
+    Directory { path: PathBuf },
+    S3 {
+        bucket: String,
+        endpoint: Option<Uri>,
+        path: String,
+    },
+}
+
+impl VaultKeyLocation {
+    pub fn from_config(vault: &VaultSection) -> anyhow::Result<Self> {
+        match (&vault.s3_bucket, &vault.key_directory) {
+            (Some(_), Some(_)) => {
+                anyhow::bail!("only one of s3_bucket and key_directory may be set")
+            }
+            (Some(bucket), None) => Ok(Self::S3 {
+                bucket: bucket.clone(),
+                endpoint: vault
+                    .s3_endpoint
+                    .as_deref()
+                    .map(|endpoint| {
+                        Uri::try_from(endpoint)
+                            .with_context(|| format!("invalid s3_endpoint {:?}", endpoint))
+                    })
+                    .transpose()?,
+                path: vault.s3_path.clone().unwrap_or_default(),
+            }),
+            (None, Some(path)) => Ok(Self::Directory { path: path.clone() }),
+            (None, None) => Ok(Self::Storage),
+        }
+    }
+}
+
+/// Returns the configuration for local storage at `path`, with every
+/// collection encrypted using the vault's master key.
+pub fn storage_configuration(
+    path: &Path,
+    vault_key: &VaultKeyLocation,
+) -> anyhow::Result<StorageConfiguration> {
+    let configuration = StorageConfiguration::new(path)
+        .with_schema::<Projects>()?
+        .default_encryption_key(KeyId::Master);
+    Ok(match vault_key {
+        VaultKeyLocation::Storage => configuration,
+        VaultKeyLocation::Directory { path } => {
+            configuration.vault_key_storage(LocalVaultKeyStorage::new(path)?)
+        }
+        VaultKeyLocation::S3 {
+            bucket,
+            endpoint,
+            path,
+        } => {
+            let mut storage = S3VaultKeyStorage::new(bucket).path(path);
+            if let Some(endpoint) = endpoint {
+                storage = storage.endpoint(Endpoint::immutable(endpoint.clone()));
+            }
+            configuration.vault_key_storage(storage)
+        }
+    })
+}
+
+/// Opens local storage at `path`, creating the database if needed.
+pub async fn open_local(
+    path: &Path,
+    vault_key: &VaultKeyLocation,
+) -> anyhow::Result<AsyncDatabase> {
+    let storage = AsyncStorage::open(storage_configuration(path, vault_key)?).await?;
+    storage
+        .create_database::<Projects>(DATABASE_NAME, true)
+        .await?;
+    Ok(storage.database::<Projects>(DATABASE_NAME).await?)
+}
+
+/// Closes local storage once the other handles to it are dropped. BonsaiDb
+/// writes any pending key-value changes when the last handle is dropped,
+/// which blocks, so it is done on a blocking thread.
+pub async fn close_local(database: AsyncDatabase) -> anyhow::Result<()> {
+    tokio::task::spawn_blocking(move || drop(database)).await?;
+    Ok(())
+}
+
+/// Connects to the BonsaiDb server at `url`, creating the database if needed.
+pub async fn connect(url: Url, certificate: Option<&Path>) -> anyhow::Result<RemoteDatabase> {
+    let mut builder = Client::build(url);
+    if let Some(certificate) = certificate {
+        builder = builder.with_certificate(Certificate::from_der(std::fs::read(certificate)?)?);
+    }
+    let client = builder.finish()?;
+    client
+        .create_database::<Projects>(DATABASE_NAME, true)
+        .await?;
+    Ok(client.database::<Projects>(DATABASE_NAME).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bonsaidb::{
+        core::connection::AsyncConnection,
+        server::{DefaultPermissions, Server, ServerConfiguration},
+    };
+    use chrono::NaiveDate;
+    use http::{HeaderMap, Uri};
+    use transmog_json::serde_json::json;
+
+    use super::*;
+    use crate::{
+        backup::SnapshotLock,
+        cache::{self, CachedPage, PageCache},
+        config::ActivitySection,
+        projects::{self, CATALOG_DIRECTORY},
+        schema::Event,
+        search, summaries,
+        testing::{self, TestDirectory},
+        updater,
+    };
+
+    /// Starts a server on localhost and connects to it the same way the
+    /// application does when `BONSAIDB_URL` is set. The server's files are
+    /// removed when the returned directory is dropped.
+    async fn remote_database(name: &str) -> anyhow::Result<(TestDirectory, RemoteDatabase)> {
+        let directory = TestDirectory::new(name);
+        // The server can't report the port it bound, so ask the system for an
+        // unused one, release it, and have the server listen on it.
+        let port = std::net::UdpSocket::bind(("::", 0))?.local_addr()?.port();
+
+        let server = Server::open(
+            ServerConfiguration::new(directory.join("server.bonsaidb"))
+                .default_permissions(DefaultPermissions::AllowAll)
+                .with_schema::<Projects>()?,
+        )
+        .await?;
+        server.install_self_signed_certificate(false).await?;
+        let certificate = directory.join("server.der");
+        std::fs::write(
+            &certificate,
+            server
+                .certificate_chain()
+                .await?
+                .into_end_entity_certificate()
+                .as_ref(),
+        )?;
+        tokio::spawn(async move { server.listen_on(port).await });
+
+        let database = connect(
+            Url::parse(&format!("bonsaidb://localhost:{}", port))?,
+            Some(&certificate),
+        )
+        .await?;
+        Ok((directory, database))
+    }
+
+    fn push_event(id: u64, created_at: &str, message: &str) -> Event {
+        testing::event(
+            id,
+            "PushEvent",
+            created_at,
+            json!({
+                "ref": "refs/heads/main",
+                "head": "b",
+                "before": "a",
+                "commits": [{
+                    "sha": format!("{:040}", id),
+                    "message": message,
+                    "author": { "name": "Jonathan Johnson", "email": "jon@khonsulabs.com" },
+                    "url": "https://api.github.com/repos/khonsulabs/bonsaidb/commits/b",
+                    "distinct": true,
+                }],
+            }),
+        )
+    }
+
+    #[tokio::test]
+    async fn catalog_over_network() -> anyhow::Result<()> {
+        let (_directory, database) = remote_database("catalog").await?;
+
+        let count = projects::reload_catalog(&database, Path::new(CATALOG_DIRECTORY)).await?;
+        let catalog = projects::catalog(&database).await?;
+        assert_eq!(catalog.len(), count);
+        assert_eq!(catalog["bonsaidb"].name, "BonsaiDb");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ingest_over_network() -> anyhow::Result<()> {
+        let (_directory, database) = remote_database("ingest").await?;
+        let activity = ActivitySection::default();
+
+        for event in [
+            push_event(1, "2022-03-01T10:00:00Z", "Add a key-value store"),
+            push_event(2, "2022-03-01T12:00:00Z", "Fix compaction of views"),
+            push_event(3, "2022-03-02T09:00:00Z", "Release v0.2.0"),
+        ] {
+            updater::insert_event(&database, event, &activity, &SnapshotLock::default()).await?;
+        }
+
+        let day = |day| NaiveDate::from_ymd(2022, 3, day);
+        let days = summaries::summaries(&database, day(1), day(2)).await?;
+        assert_eq!(
+            days.iter()
+                .map(|summary| summary.iso_date.as_str())
+                .collect::<Vec<_>>(),
+            ["2022-03-02", "2022-03-01"]
+        );
+        assert_eq!(
+            days[1].repositories["bonsaidb"].commit_authors["ecton"]["main"],
+            2
+        );
+        assert_eq!(
+            summaries::rebuild_summaries(&database, day(1), day(2), &activity).await?,
+            2
+        );
+
+        let results = search::search(&database, "compacting", 10).await?;
+        assert_eq!(results.len(), 1);
+        assert_eq!(
+            results[0].title_html,
+            "Fix <mark>compaction</mark> of views"
+        );
+        assert_eq!(database.collection::<Event>().all().count().await?, 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn page_cache_invalidated_over_network() -> anyhow::Result<()> {
+        let (_directory, database) = remote_database("invalidate").await?;
+        let cache = PageCache::new(8);
+        tokio::spawn(cache::clear_when_invalidated(
+            database.clone(),
+            cache.clone(),
+        ));
+
+        // The subscriber may not be listening yet, so publish until the
+        // cache is cleared. The cache is also cleared once the subscriber
+        // starts listening, so the second round shows that the published
+        // invalidations are received.
+        let uri = Uri::from_static("/");
+        for _ in 0..2 {
+            cache
+                .respond(&uri, &HeaderMap::new(), async {
+                    Ok(CachedPage::html(String::from("rendered")))
+                })
+                .await
+                .unwrap();
+            assert!(!cache.is_empty());
+
+            tokio::time::timeout(Duration::from_secs(10), async {
+                while !cache.is_empty() {
+                    cache::invalidate(&database).await?;
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+                anyhow::Ok(())
+            })
+            .await??;
+        }
+        Ok(())
+    }
+}
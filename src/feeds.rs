@@ -0,0 +1,457 @@
+use std::{sync::Arc, time::SystemTime};
+
+use axum::{
+    extract::{self, Extension},
+    response::Response,
+};
+use bonsaidb::core::{connection::AsyncConnection, schema::SerializedCollection};
+use chrono::{DateTime, Utc};
+use http::{HeaderMap, StatusCode, Uri};
+use serde::Serialize;
+use tera::{Context, Tera};
+use transmog_json::serde_json;
+
+use crate::{
+    api::{self, EventSource},
+    cache::{CachedPage, PageCache},
+    config::Config,
+    html,
+    projects::{self, Project},
+    schema::{Event, IssuesPayload, PullRequestPayload, ReleasePayload},
+};
+
+/// The number of entries in a feed.
+const FEED_ENTRIES: usize = 50;
+
+/// The prefix of every entry's id, which is followed by the id of the GitHub
+/// event the entry was built from.
+const ENTRY_ID_PREFIX: &str = "tag:khonsulabs.com,2022:github-event:";
+
+/// A feed of releases, closed issues and merged pull requests, built from the
+/// stored events.
+#[derive(Serialize, Debug)]
+pub struct Feed {
+    pub id: String,
+    pub title: String,
+    pub home_page_url: String,
+    pub atom_url: String,
+    pub json_url: String,
+    /// The time of the most recent entry.
+    pub updated: Option<DateTime<Utc>>,
+    pub entries: Vec<FeedEntry>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct FeedEntry {
+    pub id: String,
+    /// `release`, `issue-closed` or `pull-request-merged`.
+    pub kind: &'static str,
+    pub title: String,
+    pub url: String,
+    /// The full name of the repository (`owner/name`).
+    pub repository: String,
+    pub author: String,
+    pub author_url: String,
+    pub published: DateTime<Utc>,
+    /// The sanitized HTML of a release's description.
+    pub content_html: Option<String>,
+    /// The page of the project the repository belongs to, if it is in the
+    /// catalog.
+    pub project_url: Option<String>,
+}
+
+impl FeedEntry {
+    /// Returns the entry for `event` if it is a published release, a closed
+    /// issue or a merged pull request.
+    pub fn from_event(event: &Event) -> Option<Self> {
+        let repository = event.repository.name.clone();
+        let (kind, title, url, content_html) = match event.kind.as_str() {
+            "ReleaseEvent" => {
+                let payload =
+                    serde_json::value::from_value::<ReleasePayload>(event.payload.clone()).ok()?;
+                if payload.release.draft {
+                    return None;
+                }
+                (
+                    "release",
+                    format!("{} released {}", repository, payload.release.name),
+                    payload.release.html_url,
+                    Some(html::sanitize(&payload.release.short_description_html)),
+                )
+            }
+            "IssuesEvent" => {
+                let payload =
+                    serde_json::value::from_value::<IssuesPayload>(event.payload.clone()).ok()?;
+                if payload.action != "closed" {
+                    return None;
+                }
+                (
+                    "issue-closed",
+                    format!(
+                        "Closed {}#{}: {}",
+                        repository, payload.issue.number, payload.issue.title
+                    ),
+                    payload.issue.html_url,
+                    None,
+                )
+            }
+            "PullRequestEvent" => {
+                let payload =
+                    serde_json::value::from_value::<PullRequestPayload>(event.payload.clone())
+                        .ok()?;
+                if payload.action != "closed" || !payload.pull_request.merged {
+                    return None;
+                }
+                (
+                    "pull-request-merged",
+                    format!(
+                        "Merged {}#{}: {}",
+                        repository, payload.number, payload.pull_request.title
+                    ),
+                    payload.pull_request.html_url,
+                    None,
+                )
+            }
+            _ => return None,
+        };
+
+        Some(Self {
+            id: format!("{}{}", ENTRY_ID_PREFIX, event.id),
+            kind,
+            title,
+            url,
+            repository,
+            author: event.actor.login.clone(),
+            author_url: format!("https://github.com/{}", event.actor.login),
+            published: event.created_at,
+            content_html,
+            project_url: None,
+        })
+    }
+}
+
+/// Builds the feed of every repository, or of `project`'s repository. Returns
+/// `None` if `project` isn't in the catalog.
+pub async fn feed<C: AsyncConnection>(
+    database: &C,
+    config: &Config,
+    project: Option<&str>,
+) -> anyhow::Result<Option<Feed>> {
+    let (feed, repository) = match project {
+        Some(key) => {
+            let key = key.to_ascii_lowercase();
+            let project = match Project::get_async(&key, database).await? {
+                Some(project) => project.contents,
+                None => return Ok(None),
+            };
+            let path = format!("/projects/{}", key);
+            (
+                Feed {
+                    id: format!("tag:khonsulabs.com,2022:projects/{}/feed", key),
+                    title: format!("{} - Khonsu Labs", project.name),
+                    home_page_url: config.server.public_link(&path),
+                    atom_url: config.server.public_link(&format!("{}/feed.atom", path)),
+                    json_url: config.server.public_link(&format!("{}/feed.json", path)),
+                    updated: None,
+                    entries: Vec::new(),
+                },
+                Some(project.repository_name()),
+            )
+        }
+        None => (
+            Feed {
+                id: String::from("tag:khonsulabs.com,2022:feed"),
+                title: String::from("Khonsu Labs"),
+                home_page_url: config.server.public_link("/"),
+                atom_url: config.server.public_link("/feed.atom"),
+                json_url: config.server.public_link("/feed.json"),
+                updated: None,
+                entries: Vec::new(),
+            },
+            None,
+        ),
+    };
+
+    let source = match &repository {
+        Some(Some(repository)) => EventSource::Repository(repository),
+        // The project isn't hosted on GitHub, so it has no events.
+        Some(None) => return Ok(Some(feed)),
+        None => EventSource::All,
+    };
+    let events = api::recent_events(
+        database,
+        source,
+        SystemTime::UNIX_EPOCH..SystemTime::now(),
+        None,
+        FEED_ENTRIES,
+        |event| FeedEntry::from_event(event).is_some(),
+    )
+    .await?;
+    let catalog = projects::catalog(database).await?;
+    let entries = events
+        .iter()
+        .filter_map(|(_, event)| FeedEntry::from_event(event))
+        .map(|mut entry| {
+            let key = entry
+                .repository
+                .rsplit('/')
+                .next()
+                .unwrap_or_default()
+                .to_ascii_lowercase();
+            if catalog.contains_key(&key) {
+                entry.project_url = Some(config.server.public_link(&format!("/projects/{}", key)));
+            }
+            entry
+        })
+        .collect::<Vec<_>>();
+    Ok(Some(Feed {
+        updated: entries.first().map(|entry| entry.published),
+        entries,
+        ..feed
+    }))
+}
+
+/// A feed in the [JSON Feed](https://jsonfeed.org/version/1.1) format.
+#[derive(Serialize, Debug)]
+struct JsonFeed<'a> {
+    version: &'static str,
+    title: &'a str,
+    home_page_url: &'a str,
+    feed_url: &'a str,
+    items: Vec<JsonFeedItem<'a>>,
+}
+
+#[derive(Serialize, Debug)]
+struct JsonFeedItem<'a> {
+    id: &'a str,
+    url: &'a str,
+    title: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_html: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_text: Option<&'a str>,
+    date_published: DateTime<Utc>,
+    authors: [JsonFeedAuthor<'a>; 1],
+    tags: [&'a str; 1],
+}
+
+#[derive(Serialize, Debug)]
+struct JsonFeedAuthor<'a> {
+    name: &'a str,
+    url: &'a str,
+}
+
+impl Feed {
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(&JsonFeed {
+            version: "https://jsonfeed.org/version/1.1",
+            title: &self.title,
+            home_page_url: &self.home_page_url,
+            feed_url: &self.json_url,
+            items: self
+                .entries
+                .iter()
+                .map(|entry| JsonFeedItem {
+                    id: &entry.id,
+                    url: &entry.url,
+                    title: &entry.title,
+                    content_html: entry.content_html.as_deref(),
+                    // Every item needs content, so entries without any use
+                    // their title.
+                    content_text: entry.content_html.is_none().then_some(entry.title.as_str()),
+                    date_published: entry.published,
+                    authors: [JsonFeedAuthor {
+                        name: &entry.author,
+                        url: &entry.author_url,
+                    }],
+                    tags: [entry.kind],
+                })
+                .collect(),
+        })
+    }
+
+    pub fn to_atom(&self, templates: &Tera) -> tera::Result<String> {
+        let mut context = Context::new();
+        context.insert("feed", self);
+        templates.render("feed.xml", &context)
+    }
+}
+
+pub async fn atom_handler<C: AsyncConnection + Clone + 'static>(
+    templates: Extension<Arc<Tera>>,
+    database: Extension<C>,
+    config: Extension<Arc<Config>>,
+    cache: Extension<PageCache>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    cache
+        .respond(&uri, &headers, atom(templates, database, config, None))
+        .await
+}
+
+pub async fn project_atom_handler<C: AsyncConnection + Clone + 'static>(
+    templates: Extension<Arc<Tera>>,
+    database: Extension<C>,
+    config: Extension<Arc<Config>>,
+    cache: Extension<PageCache>,
+    extract::Path(name): extract::Path<String>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    cache
+        .respond(
+            &uri,
+            &headers,
+            atom(templates, database, config, Some(name)),
+        )
+        .await
+}
+
+async fn atom<C: AsyncConnection + Clone + 'static>(
+    templates: Extension<Arc<Tera>>,
+    database: Extension<C>,
+    config: Extension<Arc<Config>>,
+    project: Option<String>,
+) -> Result<CachedPage, (StatusCode, String)> {
+    let feed = load(&*database, &config, project.as_deref()).await?;
+    // While debugging, reload the templates always.
+    #[cfg(debug_assertions)]
+    let templates = {
+        drop(templates);
+        Tera::new("templates/**/*").map_err(internal_error)?
+    };
+    let body = feed.to_atom(&templates).map_err(internal_error)?;
+    Ok(CachedPage::new("application/atom+xml", body, feed.updated))
+}
+
+pub async fn json_handler<C: AsyncConnection + Clone + 'static>(
+    database: Extension<C>,
+    config: Extension<Arc<Config>>,
+    cache: Extension<PageCache>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    cache
+        .respond(&uri, &headers, json(database, config, None))
+        .await
+}
+
+pub async fn project_json_handler<C: AsyncConnection + Clone + 'static>(
+    database: Extension<C>,
+    config: Extension<Arc<Config>>,
+    cache: Extension<PageCache>,
+    extract::Path(name): extract::Path<String>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    cache
+        .respond(&uri, &headers, json(database, config, Some(name)))
+        .await
+}
+
+async fn json<C: AsyncConnection + Clone + 'static>(
+    database: Extension<C>,
+    config: Extension<Arc<Config>>,
+    project: Option<String>,
+) -> Result<CachedPage, (StatusCode, String)> {
+    let feed = load(&*database, &config, project.as_deref()).await?;
+    let body = feed.to_json().map_err(internal_error)?;
+    Ok(CachedPage::new("application/feed+json", body, feed.updated))
+}
+
+async fn load<C: AsyncConnection>(
+    database: &C,
+    config: &Config,
+    project: Option<&str>,
+) -> Result<Feed, (StatusCode, String)> {
+    match feed(database, config, project).await {
+        Ok(Some(feed)) => Ok(feed),
+        Ok(None) => Err((
+            StatusCode::NOT_FOUND,
+            format!("no project named {:?}", project.unwrap_or_default()),
+        )),
+        Err(err) => Err(internal_error(err)),
+    }
+}
+
+fn internal_error(err: impl ToString) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use transmog_json::serde_json::{json, Value};
+
+    use super::*;
+    use crate::testing::{self, issue};
+
+    fn event(id: u64, kind: &str, payload: Value) -> Event {
+        testing::event(id, kind, &format!("2022-03-0{}T10:00:00Z", id), payload)
+    }
+
+    #[test]
+    fn feeds_render_entries() {
+        let events = [
+            event(1, "IssuesEvent", issue("opened", 10, "Ignored")),
+            event(
+                2,
+                "IssuesEvent",
+                issue("closed", 10, "Views <sometimes> lag"),
+            ),
+            event(
+                3,
+                "ReleaseEvent",
+                json!({
+                    "action": "published",
+                    "release": {
+                        "id": 1,
+                        "name": "v0.4.0",
+                        "html_url": "https://github.com/khonsulabs/bonsaidb/releases/tag/v0.4.0",
+                        "author": {
+                            "id": 1,
+                            "login": "ecton",
+                            "url": "https://api.github.com/users/ecton",
+                            "avatar_url": "https://avatars.githubusercontent.com/u/1",
+                        },
+                        "draft": false,
+                        "prerelease": false,
+                        "short_description_html": "<p>Notes<script>alert(1)</script></p>",
+                    },
+                }),
+            ),
+        ];
+        let mut entries = events
+            .iter()
+            .filter_map(FeedEntry::from_event)
+            .collect::<Vec<_>>();
+        entries.reverse();
+        assert_eq!(
+            entries.iter().map(|entry| entry.kind).collect::<Vec<_>>(),
+            ["release", "issue-closed"]
+        );
+        assert_eq!(entries[0].id, format!("{}3", ENTRY_ID_PREFIX));
+        assert_eq!(entries[0].content_html.as_deref(), Some("<p>Notes</p>"));
+
+        let feed = Feed {
+            id: String::from("tag:khonsulabs.com,2022:feed"),
+            title: String::from("Khonsu Labs"),
+            home_page_url: String::from("https://khonsulabs.com/"),
+            atom_url: String::from("https://khonsulabs.com/feed.atom"),
+            json_url: String::from("https://khonsulabs.com/feed.json"),
+            updated: Some(entries[0].published),
+            entries,
+        };
+        let atom = feed.to_atom(&Tera::new("templates/**/*").unwrap()).unwrap();
+        assert!(atom.contains("<updated>2022-03-03T10:00:00Z</updated>"));
+        assert!(atom.contains("Views &lt;sometimes&gt; lag"));
+        assert!(atom.contains("<content type=\"html\">&lt;p&gt;Notes&lt;"));
+
+        let json: Value = serde_json::from_str(&feed.to_json().unwrap()).unwrap();
+        assert_eq!(json["items"][0]["content_html"], "<p>Notes</p>");
+        assert_eq!(
+            json["items"][1]["content_text"],
+            "Closed khonsulabs/bonsaidb#10: Views <sometimes> lag"
+        );
+    }
+}
@@ -0,0 +1,219 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+};
+
+use tera::escape_html;
+
+use crate::projects::{Project, Relationship};
+
+const NODE_HEIGHT: f64 = 36.;
+const ROW_SPACING: f64 = 90.;
+const NODE_SPACING: f64 = 24.;
+const MARGIN: f64 = 16.;
+/// An approximation of the width of each character in the node labels.
+const CHARACTER_WIDTH: f64 = 8.5;
+const NODE_PADDING: f64 = 24.;
+
+struct Node<'a> {
+    key: &'a str,
+    project: &'a Project,
+    row: usize,
+    x: f64,
+    y: f64,
+    width: f64,
+}
+
+struct Edge<'a> {
+    from: &'a str,
+    to: &'a str,
+    relationship: Relationship,
+}
+
+/// Renders the relationships between the projects in `catalog` as an SVG
+/// document. Projects are arranged in rows so that every project is drawn
+/// above the projects it is built upon, and each node links to
+/// `link(key, project)`.
+pub fn render_svg<F: Fn(&str, &Project) -> String>(
+    catalog: &BTreeMap<String, Project>,
+    link: F,
+) -> String {
+    let mut edges = Vec::new();
+    for (key, project) in catalog {
+        for (relationship, related) in project.relationships() {
+            if related != key && catalog.contains_key(related) {
+                edges.push(Edge {
+                    from: key,
+                    to: related,
+                    relationship,
+                });
+            }
+        }
+    }
+
+    // Each pair is (lower, upper): the lower project is drawn beneath the
+    // upper one.
+    let supports = edges
+        .iter()
+        .filter_map(|edge| match edge.relationship {
+            Relationship::DependsOn => Some((edge.to, edge.from)),
+            Relationship::BuiltFor => Some((edge.from, edge.to)),
+            Relationship::Supersedes => None,
+        })
+        .collect::<Vec<_>>();
+    let mut levels = HashMap::new();
+    for key in catalog.keys() {
+        level(key, &supports, &mut levels, &mut Vec::new());
+    }
+    let top_level = levels.values().copied().max().unwrap_or_default();
+
+    let mut nodes = catalog
+        .iter()
+        .map(|(key, project)| Node {
+            key,
+            project,
+            row: top_level - levels[key.as_str()],
+            x: 0.,
+            y: 0.,
+            width: project.name.chars().count() as f64 * CHARACTER_WIDTH + NODE_PADDING,
+        })
+        .collect::<Vec<_>>();
+
+    // Order each row by the average position of the already placed nodes it
+    // is connected to, which keeps most edges from crossing.
+    let mut positions = HashMap::<&str, f64>::new();
+    let mut rows = Vec::new();
+    for row in 0..=top_level {
+        let mut row_nodes = nodes
+            .iter_mut()
+            .filter(|node| node.row == row)
+            .collect::<Vec<_>>();
+        let barycenter = |key: &str| {
+            let connected = edges
+                .iter()
+                .filter_map(|edge| {
+                    if edge.from == key {
+                        positions.get(edge.to)
+                    } else if edge.to == key {
+                        positions.get(edge.from)
+                    } else {
+                        None
+                    }
+                })
+                .collect::<Vec<_>>();
+            if connected.is_empty() {
+                f64::MAX
+            } else {
+                connected.iter().copied().sum::<f64>() / connected.len() as f64
+            }
+        };
+        row_nodes.sort_by(|a, b| {
+            barycenter(a.key)
+                .total_cmp(&barycenter(b.key))
+                .then_with(|| a.key.cmp(b.key))
+        });
+
+        let mut x = 0.;
+        for node in &mut row_nodes {
+            node.x = x;
+            node.y = MARGIN + row as f64 * ROW_SPACING;
+            x += node.width + NODE_SPACING;
+            positions.insert(node.key, node.x + node.width / 2.);
+        }
+        rows.push(x - NODE_SPACING);
+    }
+
+    // Center each row.
+    let width = rows.iter().copied().fold(0., f64::max) + MARGIN * 2.;
+    for node in &mut nodes {
+        node.x += (width - rows[node.row]) / 2.;
+    }
+    let height = MARGIN * 2. + top_level as f64 * ROW_SPACING + NODE_HEIGHT;
+    let nodes = nodes
+        .into_iter()
+        .map(|node| (node.key, node))
+        .collect::<HashMap<_, _>>();
+
+    let mut svg = String::new();
+    write!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" class="project-graph" viewBox="0 0 {width:.0} {height:.0}" width="{width:.0}" height="{height:.0}">"#
+    )
+    .unwrap();
+    svg.push_str(
+        r#"<defs><marker id="arrow" viewBox="0 0 10 10" refX="10" refY="5" markerWidth="6" markerHeight="6" orient="auto-start-reverse"><path d="M 0 0 L 10 5 L 0 10 z" /></marker></defs>"#,
+    );
+    for edge in &edges {
+        let from = &nodes[edge.from];
+        let to = &nodes[edge.to];
+        let (from_y, to_y) = if from.row < to.row {
+            (from.y + NODE_HEIGHT, to.y)
+        } else if from.row > to.row {
+            (from.y, to.y + NODE_HEIGHT)
+        } else {
+            (from.y + NODE_HEIGHT / 2., to.y + NODE_HEIGHT / 2.)
+        };
+        let from_x = from.x + from.width / 2.;
+        let to_x = to.x + to.width / 2.;
+        let middle_y = (from_y + to_y) / 2.;
+        write!(
+            svg,
+            r#"<path class="edge edge-{}" d="M {from_x:.1} {from_y:.1} C {from_x:.1} {middle_y:.1}, {to_x:.1} {middle_y:.1}, {to_x:.1} {to_y:.1}" marker-end="url(#arrow)" />"#,
+            relationship_class(edge.relationship),
+        )
+        .unwrap();
+    }
+    let mut keys = nodes.keys().copied().collect::<Vec<_>>();
+    keys.sort_unstable();
+    for key in keys {
+        let node = &nodes[key];
+        write!(
+            svg,
+            r#"<a href="{}"><title>{}</title><rect class="node" x="{:.1}" y="{:.1}" width="{:.1}" height="{NODE_HEIGHT}" rx="6" /><text x="{:.1}" y="{:.1}" text-anchor="middle" dominant-baseline="central">{}</text></a>"#,
+            escape_html(&link(key, node.project)),
+            escape_html(&node.project.tagline),
+            node.x,
+            node.y,
+            node.width,
+            node.x + node.width / 2.,
+            node.y + NODE_HEIGHT / 2.,
+            escape_html(&node.project.name),
+        )
+        .unwrap();
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+/// Returns how many projects are stacked beneath `key`. Cycles are broken
+/// by ignoring the edge that completes them.
+fn level<'a>(
+    key: &'a str,
+    supports: &[(&'a str, &'a str)],
+    levels: &mut HashMap<&'a str, usize>,
+    visiting: &mut Vec<&'a str>,
+) -> usize {
+    if let Some(level) = levels.get(key) {
+        return *level;
+    }
+
+    visiting.push(key);
+    let mut level_of_key = 0;
+    for (lower, _) in supports.iter().filter(|(_, upper)| *upper == key) {
+        if !visiting.contains(lower) {
+            level_of_key = level_of_key.max(level(lower, supports, levels, visiting) + 1);
+        }
+    }
+    visiting.pop();
+
+    levels.insert(key, level_of_key);
+    level_of_key
+}
+
+fn relationship_class(relationship: Relationship) -> &'static str {
+    match relationship {
+        Relationship::DependsOn => "depends-on",
+        Relationship::BuiltFor => "built-for",
+        Relationship::Supersedes => "supersedes",
+    }
+}
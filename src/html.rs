@@ -0,0 +1,104 @@
+use std::collections::HashSet;
+
+use ammonia::Builder;
+use once_cell::sync::Lazy;
+use pulldown_cmark::{html, Options, Parser};
+
+/// The elements allowed in rendered descriptions and release notes.
+const ALLOWED_TAGS: [&str; 30] = [
+    "a",
+    "b",
+    "blockquote",
+    "br",
+    "code",
+    "del",
+    "em",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "i",
+    "img",
+    "li",
+    "ol",
+    "p",
+    "pre",
+    "strong",
+    "sub",
+    "sup",
+    "table",
+    "tbody",
+    "td",
+    "th",
+    "thead",
+    "tr",
+    "ul",
+];
+
+static SANITIZER: Lazy<Builder<'static>> = Lazy::new(|| {
+    let mut builder = Builder::empty();
+    builder
+        .tags(ALLOWED_TAGS.into_iter().collect())
+        .tag_attributes(
+            [
+                ("a", ["href", "title"].into_iter().collect::<HashSet<_>>()),
+                ("img", ["src", "alt", "title"].into_iter().collect()),
+                ("th", ["align"].into_iter().collect()),
+                ("td", ["align"].into_iter().collect()),
+            ]
+            .into_iter()
+            .collect(),
+        )
+        .url_schemes(["http", "https", "mailto"].into_iter().collect())
+        .link_rel(Some("noopener noreferrer"));
+    builder
+});
+
+/// Renders `markdown` to HTML without sanitizing it. Any HTML within the
+/// Markdown is passed through unchanged.
+pub fn markdown_to_html(markdown: &str) -> String {
+    let mut rendered = String::new();
+    html::push_html(
+        &mut rendered,
+        Parser::new_ext(
+            markdown,
+            Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH,
+        ),
+    );
+    rendered
+}
+
+/// Renders `markdown` to sanitized HTML.
+pub fn render_markdown(markdown: &str) -> String {
+    sanitize(&markdown_to_html(markdown))
+}
+
+/// Removes every element and attribute that isn't explicitly allowed from
+/// `html`, along with links using schemes other than http, https and mailto.
+pub fn sanitize(html: &str) -> String {
+    SANITIZER.clean(html).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitizes_rendered_markdown() {
+        assert_eq!(
+            render_markdown("A [link](https://khonsulabs.com) and *emphasis*."),
+            "<p>A <a href=\"https://khonsulabs.com\" rel=\"noopener noreferrer\">link</a> and <em>emphasis</em>.</p>\n"
+        );
+        assert_eq!(
+            render_markdown("<script>alert(1)</script>\n\n[link](javascript:alert(1))"),
+            "\n<p><a rel=\"noopener noreferrer\">link</a></p>\n"
+        );
+        assert_eq!(
+            sanitize("<p onclick=\"alert(1)\" class=\"lead\">Release <iframe src=\"https://example.com\"></iframe>notes</p>"),
+            "<p>Release notes</p>"
+        );
+    }
+}
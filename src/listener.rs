@@ -0,0 +1,430 @@
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use anyhow::Context as _;
+use hyper::server::accept::Accept;
+use notify::{RecursiveMode, Watcher};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, UnixListener, UnixStream},
+    sync::mpsc,
+};
+use tokio_rustls::{
+    rustls::{Certificate, PrivateKey, ServerConfig},
+    TlsAcceptor,
+};
+
+use crate::config::{BindAddress, ServerSection};
+
+/// The longest a client may take to complete a TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The number of accepted connections waiting to be served.
+const ACCEPT_BACKLOG: usize = 128;
+
+/// A stream the webserver speaks HTTP over: a TCP or Unix socket connection,
+/// possibly wrapped in TLS.
+pub trait Connection: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Connection for T {}
+
+/// The connections accepted by [`listen`], ready to be served by
+/// [`axum::Server::builder`].
+pub struct Incoming {
+    connections: mpsc::Receiver<Box<dyn Connection>>,
+    address: BindAddress,
+    unix_socket: Option<PathBuf>,
+}
+
+impl Incoming {
+    /// Returns the address being listened on. Unlike `server.bind`, a TCP
+    /// address has the actual port when port 0 was requested.
+    pub fn address(&self) -> &BindAddress {
+        &self.address
+    }
+}
+
+impl Drop for Incoming {
+    fn drop(&mut self) {
+        if let Some(path) = &self.unix_socket {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+impl Accept for Incoming {
+    type Conn = Box<dyn Connection>;
+    type Error = io::Error;
+
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        self.connections
+            .poll_recv(cx)
+            .map(|connection| connection.map(Ok))
+    }
+}
+
+/// Listens on `server.bind`, completing TLS handshakes first if a
+/// certificate is configured. Connections are accepted until the returned
+/// [`Incoming`] is dropped, which also removes a Unix socket's file.
+pub async fn listen(server: &ServerSection) -> anyhow::Result<Incoming> {
+    let tls = match (&server.tls_certificate, &server.tls_key) {
+        (Some(certificate), Some(key)) => {
+            let tls = Arc::new(TlsFiles::load(certificate.clone(), key.clone())?);
+            let watched = tls.clone();
+            tokio::spawn(async move {
+                if let Err(err) = watched.watch().await {
+                    tracing::error!("Error watching TLS certificate: {}", err);
+                }
+            });
+            Some(tls)
+        }
+        _ => None,
+    };
+
+    let (listener, address, unix_socket) = match &server.bind {
+        BindAddress::Tcp(address) => {
+            let listener = TcpListener::bind(address).await?;
+            let address = BindAddress::Tcp(listener.local_addr()?);
+            (Listener::Tcp(listener), address, None)
+        }
+        BindAddress::Unix(path) => {
+            remove_stale_socket(path).await?;
+            (
+                Listener::Unix(UnixListener::bind(path)?),
+                server.bind.clone(),
+                Some(path.clone()),
+            )
+        }
+    };
+
+    let (sender, connections) = mpsc::channel(ACCEPT_BACKLOG);
+    tokio::spawn(async move { listener.accept_connections(tls, sender).await });
+    Ok(Incoming {
+        connections,
+        address,
+        unix_socket,
+    })
+}
+
+/// Removes the socket at `path` if it was left behind by a process that
+/// didn't exit cleanly, since it would otherwise prevent binding. Anything
+/// else at `path`, including a socket another process is listening on, is
+/// left alone and reported as an error.
+async fn remove_stale_socket(path: &Path) -> anyhow::Result<()> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err).with_context(|| format!("error reading {}", path.display())),
+    };
+    if !metadata.file_type().is_socket() {
+        anyhow::bail!("{} already exists and is not a socket", path.display());
+    }
+    if UnixStream::connect(path).await.is_ok() {
+        anyhow::bail!("another process is listening on {}", path.display());
+    }
+    std::fs::remove_file(path).with_context(|| format!("error removing {}", path.display()))
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    async fn accept(&self) -> io::Result<Box<dyn Connection>> {
+        Ok(match self {
+            Listener::Tcp(listener) => {
+                let (stream, _) = listener.accept().await?;
+                stream.set_nodelay(true)?;
+                Box::new(stream)
+            }
+            Listener::Unix(listener) => Box::new(listener.accept().await?.0),
+        })
+    }
+
+    /// Accepts connections and sends them to `sender` until its receiver is
+    /// dropped. Handshakes run in their own tasks, so a slow client can't
+    /// hold up the others.
+    async fn accept_connections(
+        &self,
+        tls: Option<Arc<TlsFiles>>,
+        sender: mpsc::Sender<Box<dyn Connection>>,
+    ) {
+        loop {
+            let accepted = tokio::select! {
+                accepted = self.accept() => accepted,
+                _ = sender.closed() => return,
+            };
+            let connection = match accepted {
+                Ok(connection) => connection,
+                Err(err) => {
+                    // Errors such as running out of file descriptors are
+                    // usually temporary, so wait a moment and try again.
+                    tracing::error!("Error accepting connection: {}", err);
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            };
+
+            match &tls {
+                Some(tls) => {
+                    let acceptor = tls.acceptor();
+                    let sender = sender.clone();
+                    tokio::spawn(async move {
+                        match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(connection))
+                            .await
+                        {
+                            Ok(Ok(stream)) => {
+                                let _ = sender.send(Box::new(stream)).await;
+                            }
+                            Ok(Err(err)) => tracing::debug!("TLS handshake failed: {}", err),
+                            Err(_) => tracing::debug!("TLS handshake timed out"),
+                        }
+                    });
+                }
+                None => {
+                    if sender.send(connection).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A TLS certificate and key loaded from files, and replaced when the files
+/// change so that renewed certificates are served without a restart.
+struct TlsFiles {
+    certificate: PathBuf,
+    key: PathBuf,
+    acceptor: RwLock<TlsAcceptor>,
+}
+
+impl TlsFiles {
+    fn load(certificate: PathBuf, key: PathBuf) -> anyhow::Result<Self> {
+        let acceptor = load_acceptor(&certificate, &key)?;
+        Ok(Self {
+            certificate,
+            key,
+            acceptor: RwLock::new(acceptor),
+        })
+    }
+
+    fn acceptor(&self) -> TlsAcceptor {
+        self.acceptor.read().unwrap().clone()
+    }
+
+    /// Reloads the files each time something in their directories changes.
+    /// If they can't be loaded, the error is logged and the previous
+    /// certificate is still served.
+    async fn watch(&self) -> anyhow::Result<()> {
+        let (sender, mut changes) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = sender.send(event);
+        })?;
+        // Watching the directories rather than the files notices files that
+        // are replaced, which is how tools like certbot renew them.
+        let mut directories = vec![directory(&self.certificate), directory(&self.key)];
+        directories.dedup();
+        for directory in directories {
+            watcher.watch(directory, RecursiveMode::NonRecursive)?;
+        }
+
+        loop {
+            match changes.recv().await {
+                Some(Ok(_)) => {}
+                Some(Err(err)) => tracing::error!("Error watching TLS certificate: {}", err),
+                None => return Ok(()),
+            }
+            // The certificate and key are usually written one after the
+            // other, so wait briefly and then discard any other pending
+            // events.
+            tokio::time::sleep(Duration::from_millis(250)).await;
+            while changes.try_recv().is_ok() {}
+
+            match load_acceptor(&self.certificate, &self.key) {
+                Ok(acceptor) => {
+                    *self.acceptor.write().unwrap() = acceptor;
+                    tracing::info!("Loaded TLS certificate from {}", self.certificate.display());
+                }
+                Err(err) => tracing::error!("Error reloading TLS certificate: {:#}", err),
+            }
+        }
+    }
+}
+
+fn directory(path: &Path) -> &Path {
+    path.parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."))
+}
+
+fn load_acceptor(certificate: &Path, key: &Path) -> anyhow::Result<TlsAcceptor> {
+    let certificates = rustls_pemfile::certs(&mut BufReader::new(
+        File::open(certificate)
+            .with_context(|| format!("error opening {}", certificate.display()))?,
+    ))?;
+    if certificates.is_empty() {
+        anyhow::bail!("no certificates found in {}", certificate.display());
+    }
+
+    let mut reader = BufReader::new(
+        File::open(key).with_context(|| format!("error opening {}", key.display()))?,
+    );
+    let key = loop {
+        match rustls_pemfile::read_one(&mut reader)? {
+            Some(
+                rustls_pemfile::Item::RSAKey(key)
+                | rustls_pemfile::Item::PKCS8Key(key)
+                | rustls_pemfile::Item::ECKey(key),
+            ) => break PrivateKey(key),
+            Some(_) => {}
+            None => anyhow::bail!("no private key found in {}", key.display()),
+        }
+    };
+
+    let mut config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certificates.into_iter().map(Certificate).collect(), key)?;
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::get, Router};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+    use tokio_rustls::{
+        rustls::{ClientConfig, RootCertStore, ServerName},
+        TlsConnector,
+    };
+
+    use std::net::SocketAddr;
+
+    use super::*;
+    use crate::testing::TestDirectory;
+
+    /// Serves a page saying hello, returning the address it's served on.
+    async fn serve(server: &ServerSection) -> anyhow::Result<BindAddress> {
+        let incoming = listen(server).await?;
+        let address = incoming.address().clone();
+        let app = Router::new().route("/", get(|| async { "hello" }));
+        tokio::spawn(axum::Server::builder(incoming).serve(app.into_make_service()));
+        Ok(address)
+    }
+
+    async fn request(mut stream: impl AsyncRead + AsyncWrite + Unpin) -> anyhow::Result<String> {
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        Ok(response)
+    }
+
+    #[tokio::test]
+    async fn unix_socket() -> anyhow::Result<()> {
+        let directory = TestDirectory::new("unix-socket-test");
+        let socket = directory.join("projects.sock");
+        let server = ServerSection {
+            bind: BindAddress::Unix(socket.clone()),
+            ..ServerSection::default()
+        };
+
+        // A socket left behind by an earlier process is replaced.
+        drop(std::os::unix::net::UnixListener::bind(&socket)?);
+        serve(&server).await?;
+        let response = request(UnixStream::connect(&socket).await?).await?;
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.ends_with("hello"), "{}", response);
+
+        // A socket that is still being listened on isn't.
+        assert!(listen(&server).await.is_err());
+        assert!(request(UnixStream::connect(&socket).await?)
+            .await?
+            .ends_with("hello"));
+
+        // Neither is a file that isn't a socket.
+        let file = directory.join("projects.txt");
+        std::fs::write(&file, "keep me")?;
+        let server = ServerSection {
+            bind: BindAddress::Unix(file.clone()),
+            ..ServerSection::default()
+        };
+        assert!(listen(&server).await.is_err());
+        assert_eq!(std::fs::read_to_string(&file)?, "keep me");
+        Ok(())
+    }
+
+    /// Writes a new self-signed certificate for `localhost` and returns it in
+    /// DER form.
+    fn write_certificate(certificate: &Path, key: &Path) -> anyhow::Result<Vec<u8>> {
+        let generated = rcgen::generate_simple_self_signed(vec![String::from("localhost")])?;
+        std::fs::write(key, generated.serialize_private_key_pem())?;
+        std::fs::write(certificate, generated.serialize_pem()?)?;
+        Ok(generated.serialize_der()?)
+    }
+
+    async fn request_trusting(address: SocketAddr, certificate: &[u8]) -> anyhow::Result<String> {
+        let mut roots = RootCertStore::empty();
+        roots.add(&Certificate(certificate.to_vec()))?;
+        let connector = TlsConnector::from(Arc::new(
+            ClientConfig::builder()
+                .with_safe_defaults()
+                .with_root_certificates(roots)
+                .with_no_client_auth(),
+        ));
+        let stream = TcpStream::connect(address).await?;
+        let stream = connector
+            .connect(ServerName::try_from("localhost")?, stream)
+            .await?;
+        request(stream).await
+    }
+
+    #[tokio::test]
+    async fn tls_certificates_are_reloaded() -> anyhow::Result<()> {
+        let directory = TestDirectory::new("tls-test");
+        let certificate = directory.join("certificate.pem");
+        let key = directory.join("key.pem");
+        let first = write_certificate(&certificate, &key)?;
+        let address = match serve(&ServerSection {
+            bind: "127.0.0.1:0".parse()?,
+            tls_certificate: Some(certificate.clone()),
+            tls_key: Some(key.clone()),
+            ..ServerSection::default()
+        })
+        .await?
+        {
+            BindAddress::Tcp(address) => address,
+            BindAddress::Unix(_) => unreachable!(),
+        };
+
+        let response = request_trusting(address, &first).await?;
+        assert!(response.ends_with("hello"), "{}", response);
+
+        let second = write_certificate(&certificate, &key)?;
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while request_trusting(address, &second).await.is_err() {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await?;
+        assert!(request_trusting(address, &first).await.is_err());
+        Ok(())
+    }
+}
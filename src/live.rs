@@ -0,0 +1,473 @@
+use std::{collections::VecDeque, convert::Infallible, sync::Arc};
+
+use axum::{
+    extract::{self, Extension},
+    response::sse::{Event as SseEvent, KeepAlive, Sse},
+};
+use bonsaidb::core::{
+    connection::{AsyncConnection, Range},
+    pubsub::{AsyncPubSub, AsyncSubscriber},
+    schema::SerializedCollection,
+};
+use futures::{stream, Stream, StreamExt};
+use http::{HeaderMap, StatusCode};
+use serde::{Deserialize, Serialize};
+use tera::{Context, Tera};
+use tokio::sync::broadcast::{self, error::RecvError};
+use transmog_json::serde_json;
+
+use crate::{
+    backoff::Backoff, config::Config, projects, schema::Event, shutdown::Shutdown,
+    summaries::DailySummary,
+};
+
+/// The PubSub topic the id of each newly stored event is published to.
+pub const EVENTS_TOPIC: &str = "events-ingested";
+
+/// The most events replayed to a client that reconnects or falls behind. A
+/// client that missed more is asked to reload the page instead.
+const REPLAY_LIMIT: usize = 100;
+
+/// The number of updates buffered for each client. A client that hasn't
+/// read them by the time more arrive has fallen behind, and catches up by
+/// replaying the stored events.
+const CLIENT_BUFFER: usize = 64;
+
+/// The activity a newly stored event adds to the activity page.
+#[derive(Serialize, Debug)]
+pub struct LiveActivity {
+    pub iso_date: String,
+    pub display: String,
+    pub repository: String,
+    /// The repository's block for the day, rendered from
+    /// `activity_repository.html`.
+    pub html: String,
+}
+
+/// A [`LiveActivity`], serialized once and shared by every client.
+#[derive(Debug)]
+struct LiveUpdate {
+    /// The id of the event's document. Document ids increase as events are
+    /// stored, so they are used as the ids of the server-sent events.
+    id: u64,
+    data: String,
+}
+
+/// Sends the activity of newly stored events to every connected client.
+#[derive(Clone, Debug)]
+pub struct LiveUpdates {
+    sender: broadcast::Sender<Arc<LiveUpdate>>,
+}
+
+impl Default for LiveUpdates {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(CLIENT_BUFFER);
+        Self { sender }
+    }
+}
+
+/// Tells every running webserver that the event with document `id` was
+/// stored.
+pub async fn publish<C: AsyncPubSub>(database: &C, id: u64) -> anyhow::Result<()> {
+    database.publish(&EVENTS_TOPIC, &id).await?;
+    Ok(())
+}
+
+/// Returns the id of the most recently stored event.
+pub async fn latest_event_id<C: AsyncConnection>(database: &C) -> anyhow::Result<u64> {
+    let all: Range<u64> = (..).into();
+    let latest = Event::list_async(all, database)
+        .descending()
+        .limit(1)
+        .await?;
+    Ok(latest
+        .into_iter()
+        .next()
+        .map_or(0, |document| document.header.id))
+}
+
+/// Renders the activity `event` adds to the activity page. Returns None if
+/// the event isn't shown, such as an event from a bot.
+async fn render<C: AsyncConnection>(
+    database: &C,
+    templates: &Tera,
+    config: &Config,
+    id: u64,
+    event: &Event,
+) -> anyhow::Result<Option<LiveUpdate>> {
+    let mut summary = DailySummary::new(event.created_at.date().naive_utc());
+    summary.record(event, &config.activity)?;
+    let (repository, active) = match summary.repositories.into_iter().next() {
+        Some(repository) => repository,
+        None => return Ok(None),
+    };
+
+    let mut catalog = projects::catalog(database).await?;
+    catalog.retain(|key, _| key == &repository);
+    let mut context = Context::new();
+    context.insert(
+        "lifecycles",
+        &projects::lifecycles(database, &catalog).await?,
+    );
+    context.insert("projects", &catalog);
+    context.insert("repository", &repository);
+    context.insert("summary", &active);
+    let activity = LiveActivity {
+        iso_date: summary.iso_date,
+        display: summary.display,
+        html: templates.render("activity_repository.html", &context)?,
+        repository,
+    };
+    Ok(Some(LiveUpdate {
+        id,
+        data: serde_json::to_string(&activity)?,
+    }))
+}
+
+/// Renders each event published to [`EVENTS_TOPIC`] once, and sends it to
+/// the connected clients. If the subscription fails, it is retried until it
+/// succeeds, and then the events stored in the meantime are sent.
+pub async fn broadcast_stored_events<C: AsyncConnection + AsyncPubSub>(
+    database: C,
+    templates: Arc<Tera>,
+    config: Arc<Config>,
+    updates: LiveUpdates,
+) {
+    let mut broadcaster = Broadcaster {
+        database,
+        templates,
+        config,
+        updates,
+        last_id: None,
+    };
+    let mut backoff = Backoff::default();
+    loop {
+        if let Err(err) = broadcaster.receive(&mut backoff).await {
+            tracing::error!("Error receiving stored events: {}", err);
+        }
+        backoff.wait().await;
+    }
+}
+
+/// The state of [`broadcast_stored_events()`].
+struct Broadcaster<C> {
+    database: C,
+    templates: Arc<Tera>,
+    config: Arc<Config>,
+    updates: LiveUpdates,
+    /// The id of the last event published to [`EVENTS_TOPIC`] that was
+    /// received.
+    last_id: Option<u64>,
+}
+
+impl<C: AsyncConnection + AsyncPubSub> Broadcaster<C> {
+    /// Subscribes to [`EVENTS_TOPIC`] and broadcasts each event published to
+    /// it until the subscription fails.
+    async fn receive(&mut self, backoff: &mut Backoff) -> anyhow::Result<()> {
+        let subscriber = self.database.create_subscriber().await?;
+        subscriber.subscribe_to(&EVENTS_TOPIC).await?;
+        backoff.reset();
+
+        if let Some(last_id) = self.last_id {
+            // Clients skip the events they've already been sent, so it's
+            // fine if some of these were received before.
+            let missed: Range<u64> = (last_id.saturating_add(1)..).into();
+            let events = Event::list_async(missed, &self.database)
+                .limit(u32::try_from(REPLAY_LIMIT)?)
+                .await?;
+            for document in events {
+                self.broadcast(document.header.id).await;
+            }
+        }
+
+        loop {
+            let message = subscriber.receiver().receive_async().await?;
+            match message.payload::<u64>() {
+                Ok(id) => self.broadcast(id).await,
+                Err(err) => tracing::error!("Error reading a stored event's id: {}", err),
+            }
+        }
+    }
+
+    /// Sends the activity of the event with document `id` to the connected
+    /// clients. Errors are logged so that the next event is still sent.
+    async fn broadcast(&mut self, id: u64) {
+        self.last_id = self.last_id.max(Some(id));
+        if self.updates.sender.receiver_count() == 0 {
+            return;
+        }
+        match self.render(id).await {
+            Ok(Some(update)) => {
+                // Sending only fails when every client has disconnected.
+                let _ = self.updates.sender.send(Arc::new(update));
+            }
+            Ok(None) => {}
+            Err(err) => tracing::error!("Error rendering live activity for event {}: {}", id, err),
+        }
+    }
+
+    async fn render(&self, id: u64) -> anyhow::Result<Option<LiveUpdate>> {
+        let event = match Event::get_async(id, &self.database).await? {
+            Some(document) => document.contents,
+            None => return Ok(None),
+        };
+
+        // While debugging, reload the templates always.
+        let reloaded;
+        let templates = if cfg!(debug_assertions) {
+            reloaded = Tera::new("templates/**/*")?;
+            &reloaded
+        } else {
+            &*self.templates
+        };
+        render(&self.database, templates, &self.config, id, &event).await
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StreamQuery {
+    /// The id of the latest event shown on the page that opened the stream.
+    pub after: Option<u64>,
+}
+
+/// Streams the activity of newly stored events as server-sent events.
+///
+/// Each event's id is the id of its document. A reconnecting client's
+/// `Last-Event-ID` header, or the `after` parameter for the first
+/// connection, is used to replay the events it missed.
+pub async fn stream_handler<C: AsyncConnection + Clone + 'static>(
+    templates: Extension<Arc<Tera>>,
+    database: Extension<C>,
+    config: Extension<Arc<Config>>,
+    updates: Extension<LiveUpdates>,
+    shutdown: Extension<Shutdown>,
+    extract::Query(query): extract::Query<StreamQuery>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<SseEvent, Infallible>>>, (StatusCode, String)> {
+    let last_event_id = match headers.get("last-event-id") {
+        Some(value) => Some(
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.parse::<u64>().ok())
+                .ok_or_else(|| {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        String::from("Last-Event-ID must be an event id"),
+                    )
+                })?,
+        ),
+        None => query.after,
+    };
+
+    // Subscribe before reading any stored events so that none are missed.
+    let mut client = StreamClient {
+        receiver: updates.sender.subscribe(),
+        database: database.0,
+        templates: templates.0,
+        config: config.0,
+        last_id: 0,
+        pending: VecDeque::new(),
+        reload: false,
+    };
+    match last_event_id {
+        Some(last_id) => {
+            client.last_id = last_id;
+            client.replay().await.map_err(internal_error)?;
+        }
+        None => {
+            client.last_id = latest_event_id(&client.database)
+                .await
+                .map_err(internal_error)?;
+        }
+    }
+
+    // Streams never finish on their own, so they are ended when shutdown is
+    // requested to let the webserver stop. Browsers reconnect and replay
+    // what they missed from the next process.
+    Ok(Sse::new(
+        stream::unfold(Some(client), |client| async move {
+            let mut client = client?;
+            let event = client.next().await;
+            Some((Ok(event), (!client.reload).then_some(client)))
+        })
+        .take_until(shutdown.0.clone().requested()),
+    )
+    .keep_alive(KeepAlive::default()))
+}
+
+fn internal_error(err: anyhow::Error) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+}
+
+/// The state of one client's stream.
+struct StreamClient<C> {
+    receiver: broadcast::Receiver<Arc<LiveUpdate>>,
+    database: C,
+    templates: Arc<Tera>,
+    config: Arc<Config>,
+    /// The id of the last event sent to the client.
+    last_id: u64,
+    /// Updates waiting to be sent, oldest first.
+    pending: VecDeque<Arc<LiveUpdate>>,
+    /// True once the client has missed too many events to catch up.
+    reload: bool,
+}
+
+impl<C: AsyncConnection> StreamClient<C> {
+    /// Waits for the next update to send. When the client must reload the
+    /// page, a `reload` event is returned and the stream ends after it.
+    async fn next(&mut self) -> SseEvent {
+        loop {
+            if self.reload {
+                return SseEvent::default().event("reload").data("");
+            }
+
+            if let Some(update) = self.pending.pop_front() {
+                if update.id > self.last_id {
+                    self.last_id = update.id;
+                    return SseEvent::default()
+                        .id(update.id.to_string())
+                        .event("activity")
+                        .data(&update.data);
+                }
+                continue;
+            }
+
+            match self.receiver.recv().await {
+                Ok(update) => self.pending.push_back(update),
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::debug!("A live activity client skipped {} updates", skipped);
+                    if let Err(err) = self.replay().await {
+                        tracing::error!("Error replaying live activity: {}", err);
+                        self.reload = true;
+                    }
+                }
+                Err(RecvError::Closed) => self.reload = true,
+            }
+        }
+    }
+
+    /// Queues the activity of the events stored after `last_id`, ahead of
+    /// any pending updates. If more than [`REPLAY_LIMIT`] events were
+    /// missed, the client is asked to reload instead.
+    async fn replay(&mut self) -> anyhow::Result<()> {
+        let missed: Range<u64> = (self.last_id.saturating_add(1)..).into();
+        let events = Event::list_async(missed, &self.database)
+            .limit(u32::try_from(REPLAY_LIMIT + 1)?)
+            .await?;
+        if events.len() > REPLAY_LIMIT {
+            self.reload = true;
+            return Ok(());
+        }
+
+        let templates = self.templates.clone();
+        // While debugging, reload the templates always.
+        #[cfg(debug_assertions)]
+        let templates = {
+            drop(templates);
+            Tera::new("templates/**/*")?
+        };
+
+        let mut replayed = VecDeque::new();
+        for document in &events {
+            let id = document.header.id;
+            match render(
+                &self.database,
+                &templates,
+                &self.config,
+                id,
+                &document.contents,
+            )
+            .await
+            {
+                Ok(Some(update)) => replayed.push_back(Arc::new(update)),
+                Ok(None) => {}
+                Err(err) => {
+                    tracing::error!("Error rendering live activity for event {}: {}", id, err)
+                }
+            }
+        }
+        replayed.append(&mut self.pending);
+        self.pending = replayed;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::{self, VaultKeyLocation},
+        testing::{self, TestDirectory},
+    };
+
+    fn event(id: u64, kind: &str) -> Event {
+        testing::event(
+            id,
+            kind,
+            "2022-03-01T10:00:00Z",
+            testing::issue("closed", id, "An issue"),
+        )
+    }
+
+    #[tokio::test]
+    async fn clients_replay_missed_events() -> anyhow::Result<()> {
+        let directory = TestDirectory::new("live-test");
+        let database = database::open_local(
+            &directory.join("projects.bonsaidb"),
+            &VaultKeyLocation::Storage,
+        )
+        .await?;
+
+        let mut ids = Vec::new();
+        for (id, kind) in [(1, "IssuesEvent"), (2, "WatchEvent"), (3, "IssuesEvent")] {
+            ids.push(event(id, kind).push_into_async(&database).await?.header.id);
+        }
+        assert_eq!(latest_event_id(&database).await?, ids[2]);
+
+        let updates = LiveUpdates::default();
+        let mut client = StreamClient {
+            receiver: updates.sender.subscribe(),
+            database: database.clone(),
+            templates: Arc::new(Tera::new("templates/**/*")?),
+            config: Arc::new(Config::default()),
+            last_id: ids[0],
+            pending: VecDeque::new(),
+            reload: false,
+        };
+
+        // The watch event isn't shown, so only the second issue is replayed.
+        client.replay().await?;
+        assert_eq!(client.pending.len(), 1);
+        let activity = serde_json::from_str::<serde_json::Value>(&client.pending[0].data)?;
+        assert_eq!(activity["iso_date"], "2022-03-01");
+        assert_eq!(activity["repository"], "bonsaidb");
+        assert!(activity["html"].as_str().unwrap().contains("An issue"));
+        client.next().await;
+        assert_eq!(client.last_id, ids[2]);
+
+        // Updates that were already replayed are skipped.
+        updates.sender.send(Arc::new(LiveUpdate {
+            id: ids[2],
+            data: String::new(),
+        }))?;
+        updates.sender.send(Arc::new(LiveUpdate {
+            id: ids[2] + 1,
+            data: String::new(),
+        }))?;
+        client.next().await;
+        assert_eq!(client.last_id, ids[2] + 1);
+
+        // A client that missed too much is asked to reload. The first of
+        // these events is stored as the id the client just received.
+        for id in 0..=REPLAY_LIMIT as u64 + 1 {
+            event(100 + id, "WatchEvent")
+                .push_into_async(&database)
+                .await?;
+        }
+        client.replay().await?;
+        assert!(client.reload);
+        Ok(())
+    }
+}
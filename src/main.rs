@@ -1,62 +1,390 @@
-use std::env;
-
-use bonsaidb::{
-    core::connection::AsyncStorageConnection,
-    keystorage::s3::{aws_sdk_s3::Endpoint, S3VaultKeyStorage},
-    local::{
-        config::{Builder, StorageConfiguration},
-        AsyncStorage,
-    },
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
 };
-use http::Uri;
 
-use crate::schema::Projects;
+use bonsaidb::core::{connection::AsyncConnection, pubsub::AsyncPubSub};
+use futures::{stream::FuturesUnordered, StreamExt};
+use structopt::StructOpt;
+use tokio::task::JoinHandle;
+
+use crate::{
+    backup::{BackupCommand, BackupConfiguration, SnapshotLock},
+    config::{BindAddress, Config, LogFormat},
+    database::{DatabaseConfiguration, VaultKeyLocation},
+    manifests::ManifestCommand,
+    projects::{ProjectCommand, CATALOG_DIRECTORY},
+    shutdown::Shutdown,
+    summaries::SummaryCommand,
+    vault::VaultCommand,
+};
 
+mod activity;
+mod api;
+mod archive;
+mod backoff;
+mod backup;
+mod cache;
+mod check;
+mod config;
+mod database;
+mod feeds;
+mod graph;
+mod html;
+mod listener;
+mod live;
+mod manifests;
 mod projects;
 mod schema;
+mod search;
+mod shutdown;
+mod summaries;
+#[cfg(test)]
+mod testing;
 mod updater;
+mod vault;
 mod webserver;
 
+#[derive(StructOpt, Debug)]
+struct Args {
+    /// The configuration file to read. Defaults to `khonsulabs-projects.toml`
+    /// if it exists.
+    #[structopt(long, global = true, parse(from_os_str))]
+    config: Option<PathBuf>,
+    /// The directory of local storage, overriding `database.path`.
+    #[structopt(long, global = true, parse(from_os_str))]
+    database: Option<PathBuf>,
+    /// The address the webserver listens on, overriding `server.bind`: an
+    /// address such as `127.0.0.1:3000`, or `unix:` followed by the path of a
+    /// Unix domain socket.
+    #[structopt(long, global = true)]
+    bind: Option<BindAddress>,
+    /// How log messages are written, overriding `log.format`: `pretty`,
+    /// `compact` or `json`.
+    #[structopt(long, global = true)]
+    log_format: Option<LogFormat>,
+    /// Runs the webserver and the background jobs if omitted.
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Runs the webserver, reloading the project catalog when it changes.
+    Serve,
+    /// Runs the background jobs: fetching events from GitHub, updating crate
+    /// manifests and, for local storage, scheduled backups.
+    Update,
+    /// Fetches new events from GitHub once.
+    UpdateOnce,
+    /// Fetches every event GitHub still returns and inserts any that are
+    /// missing, instead of stopping at the first event that is already
+    /// stored.
+    Backfill,
+    /// Writes every stored event as JSON, one event per line.
+    Export {
+        /// The file to write to. Defaults to standard output.
+        #[structopt(long, short, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Inserts the events from a file written by `export`, skipping any that
+    /// are already stored.
+    Import {
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+    },
+    /// Checks the project files for problems and lists active repositories
+    /// that are missing from the catalog.
+    #[structopt(alias = "check-projects")]
+    Check,
+    /// Views or reloads the project catalog.
+    Project(ProjectCommand),
+    /// Updates or shows the dependencies between projects' crates.
+    Manifests(ManifestCommand),
+    /// Rebuilds the daily activity summaries.
+    Summaries(SummaryCommand),
+    /// Creates, lists or restores snapshots of local storage.
+    Backup(BackupCommand),
+    /// Manages the keys encrypting local storage.
+    Vault(VaultCommand),
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    dotenv::dotenv()?;
+    let args = Args::from_args();
+    // The environment can also be set in `.env`, which is optional.
+    dotenv::dotenv().ok();
+    let mut config = Config::load(args.config.as_deref())?;
+    if let Some(path) = args.database {
+        config.database.path = path;
+    }
+    if let Some(bind) = args.bind {
+        config.server.bind = bind;
+    }
+    if let Some(format) = args.log_format {
+        config.log.format = format;
+    }
+    config.validate()?;
+
     // initialize tracing
-    tracing_subscriber::fmt()
-        .pretty()
-        // enable everything
-        // .with_max_level(tracing::Level::TRACE)
-        // .with_span_events(FmtSpan::ENTER)
-        // sets this to be the default, global collector for this application.
-        .init();
-
-    let mut configuration =
-        StorageConfiguration::new("projects.bonsaidb").with_schema::<Projects>()?;
-    if let Ok(bucket) = env::var("VAULT_S3_BUCKET") {
-        configuration = configuration.vault_key_storage(
-            S3VaultKeyStorage::new(bucket)
-                .endpoint(Endpoint::immutable(Uri::try_from(env::var(
-                    "VAULT_S3_ENDPOINT",
-                )?)?))
-                .path(env::var("VAULT_S3_PATH")?),
-        );
-    }
-
-    let storage = AsyncStorage::open(configuration).await?;
-    storage
-        .create_database::<Projects>("projects", true)
-        .await?;
-    let database = storage.database::<Projects>("projects").await?;
-
-    let task_database = database.clone();
-    let updater = tokio::spawn(async move {
-        updater::update_events_periodically(task_database.clone())
+    let subscriber = tracing_subscriber::fmt();
+    // enable everything
+    // .with_max_level(tracing::Level::TRACE)
+    // .with_span_events(FmtSpan::ENTER)
+    match config.log.format {
+        LogFormat::Pretty => subscriber.pretty().init(),
+        LogFormat::Compact => subscriber.compact().init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+    if config.github.token.is_none() {
+        tracing::warn!("No GitHub token is configured, so requests to GitHub are unauthenticated");
+    }
+
+    // The webserver, the background jobs and the commands that fetch events
+    // stop cleanly on SIGINT or SIGTERM. The signals end other commands as
+    // usual.
+    let shutdown = if matches!(
+        args.command,
+        None | Some(Command::Serve | Command::Update | Command::UpdateOnce | Command::Backfill)
+    ) {
+        Shutdown::on_signals()?
+    } else {
+        Shutdown::never()
+    };
+
+    let config = Arc::new(config);
+    match DatabaseConfiguration::from_config(&config.database)? {
+        DatabaseConfiguration::Local { path } => {
+            match args.command {
+                Some(Command::Backup(command)) => return command.execute(&path, &config).await,
+                Some(Command::Vault(command)) => {
+                    return command.execute(&path, &config.vault).await
+                }
+                _ => {}
+            }
+
+            let vault_key = VaultKeyLocation::from_config(&config.vault)?;
+            let database = database::open_local(&path, &vault_key).await?;
+            let snapshots = SnapshotLock::default();
+            let mut tasks = Vec::new();
+            if matches!(args.command, None | Some(Command::Update)) {
+                if let Some(configuration) = BackupConfiguration::from_config(&config.backup)? {
+                    let storage = database.storage();
+                    tasks.push(tokio::spawn(backup::back_up_periodically(
+                        storage,
+                        configuration,
+                        snapshots.clone(),
+                        shutdown.clone(),
+                    )));
+                }
+            }
+            // The storage is closed even if a task failed, so that pending
+            // changes are written.
+            let result = run(
+                database.clone(),
+                args.command,
+                config,
+                snapshots,
+                shutdown,
+                tasks,
+            )
+            .await;
+            database::close_local(database).await?;
+            result
+        }
+        DatabaseConfiguration::Server { url, certificate } => {
+            if matches!(args.command, Some(Command::Backup(_) | Command::Vault(_))) {
+                anyhow::bail!("backups and vault keys are managed by the server");
+            }
+            run(
+                database::connect(url, certificate.as_deref()).await?,
+                args.command,
+                config,
+                SnapshotLock::default(),
+                shutdown,
+                Vec::new(),
+            )
             .await
-            .unwrap();
-    });
-    let server = tokio::spawn(async move {
-        webserver::serve(database).await.unwrap();
-    });
-
-    tokio::try_join!(updater, server)?;
-    Ok(())
+        }
+    }
+}
+
+/// Executes `command`. The webserver and the background jobs run until one of
+/// them fails or shutdown is requested, and both run if no command was given.
+/// `tasks` are background jobs that were already started, which are stopped
+/// along with the others. The updater pauses while snapshots are taken using
+/// `snapshots`.
+async fn run<C: AsyncConnection + AsyncPubSub + Clone + 'static>(
+    database: C,
+    command: Option<Command>,
+    config: Arc<Config>,
+    snapshots: SnapshotLock,
+    shutdown: Shutdown,
+    mut tasks: Vec<JoinHandle<anyhow::Result<()>>>,
+) -> anyhow::Result<()> {
+    let (serve, update) = match command {
+        None => (true, true),
+        Some(Command::Serve) => (true, false),
+        Some(Command::Update) => (false, true),
+        Some(Command::UpdateOnce) => {
+            let inserted = updater::update_once(&database, &config, false, &shutdown).await?;
+            println!("Inserted {} new events", inserted);
+            return Ok(());
+        }
+        Some(Command::Backfill) => {
+            let inserted = updater::update_once(&database, &config, true, &shutdown).await?;
+            println!("Inserted {} missing events", inserted);
+            return Ok(());
+        }
+        Some(Command::Export { output }) => {
+            let exported = match output {
+                Some(path) => {
+                    updater::export_events(&database, BufWriter::new(File::create(path)?)).await?
+                }
+                None => updater::export_events(&database, io::stdout().lock()).await?,
+            };
+            eprintln!("Exported {} events", exported);
+            return Ok(());
+        }
+        Some(Command::Import { input }) => {
+            let inserted = updater::import_events(
+                &database,
+                BufReader::new(File::open(input)?),
+                &config.activity,
+            )
+            .await?;
+            println!("Imported {} new events", inserted);
+            return Ok(());
+        }
+        Some(Command::Check) => {
+            return check::check_projects(&database, Path::new(CATALOG_DIRECTORY), &config.activity)
+                .await
+        }
+        Some(Command::Project(command)) => return command.execute(&database).await,
+        Some(Command::Manifests(command)) => {
+            return command.execute(&database, &config.github).await
+        }
+        Some(Command::Summaries(command)) => {
+            return command.execute(&database, &config.activity).await
+        }
+        Some(Command::Backup(_) | Command::Vault(_)) => {
+            unreachable!("local storage commands are handled before connecting")
+        }
+    };
+
+    let timeout = Duration::from_secs(config.shutdown.timeout_secs);
+    if update {
+        tasks.push(tokio::spawn(updater::update_events_periodically(
+            database.clone(),
+            config.clone(),
+            snapshots,
+            shutdown.clone(),
+        )));
+        tasks.push(tokio::spawn(manifests::update_manifests_periodically(
+            database.clone(),
+            config.github.clone(),
+            shutdown.clone(),
+        )));
+    }
+    if serve {
+        tasks.push(tokio::spawn(projects::watch_catalog(
+            database.clone(),
+            PathBuf::from(CATALOG_DIRECTORY),
+            shutdown.clone(),
+        )));
+        tasks.push(tokio::spawn(webserver::serve(
+            database,
+            config,
+            shutdown.clone(),
+        )));
+    }
+
+    wait_for_tasks(tasks, shutdown, timeout).await
+}
+
+/// Waits for `tasks` to finish. If one of them fails, shutdown is requested
+/// so that the others stop too. Once shutdown is requested, the tasks have
+/// `timeout` to finish before they are aborted. Returns the first task's
+/// error.
+async fn wait_for_tasks(
+    tasks: Vec<JoinHandle<anyhow::Result<()>>>,
+    shutdown: Shutdown,
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    let mut running = tasks.into_iter().collect::<FuturesUnordered<_>>();
+    let deadline = {
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown.requested().await;
+            tokio::time::sleep(timeout).await;
+        }
+    };
+    tokio::pin!(deadline);
+
+    let mut failure = None;
+    loop {
+        tokio::select! {
+            finished = running.next() => match finished {
+                Some(result) => {
+                    if let Err(err) = result.map_err(anyhow::Error::from).and_then(|result| result) {
+                        tracing::error!("A task failed: {:#}", err);
+                        if failure.is_none() {
+                            failure = Some(err);
+                            shutdown.request();
+                        }
+                    }
+                }
+                None => break,
+            },
+            _ = &mut deadline => {
+                tracing::warn!(
+                    "Aborting the tasks that didn't stop within {} seconds",
+                    timeout.as_secs()
+                );
+                for task in running.iter() {
+                    task.abort();
+                }
+                while running.next().await.is_some() {}
+                break;
+            }
+        }
+    }
+    failure.map_or(Ok(()), Err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn failing_tasks_stop_the_others() {
+        let shutdown = Shutdown::never();
+        let waiting = shutdown.clone();
+        let stubborn = Shutdown::never();
+        let tasks = vec![
+            tokio::spawn(async move {
+                waiting.requested().await;
+                Ok(())
+            }),
+            tokio::spawn(async { Err(anyhow::anyhow!("failed")) }),
+            tokio::spawn(async move {
+                stubborn.requested().await;
+                Ok(())
+            }),
+        ];
+
+        // The first task stops once shutdown is requested, and the last one
+        // is aborted after the timeout.
+        let result = tokio::time::timeout(
+            Duration::from_secs(10),
+            wait_for_tasks(tasks, shutdown.clone(), Duration::from_millis(50)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.unwrap_err().to_string(), "failed");
+        assert!(shutdown.is_requested());
+    }
 }
@@ -0,0 +1,688 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::PathBuf,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use bonsaidb::core::{
+    connection::AsyncConnection,
+    pubsub::AsyncPubSub,
+    schema::{Collection, CollectionName, Qualified, Schematic, SerializedCollection},
+};
+use chrono::{DateTime, Utc};
+use reqwest::{Client, StatusCode};
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use structopt::StructOpt;
+use toml::Value;
+use transmog_json::{serde_json, Json};
+
+use crate::{cache, config::GitHubSection, projects, shutdown::Shutdown, updater::github_request};
+
+/// The crates defined in a repository's Cargo manifests, and which of our
+/// other crates they depend on. Stored using the project's key as the primary
+/// key.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RepositoryManifest {
+    /// The full name of the repository (`owner/name`).
+    pub repository: String,
+    pub crates: Vec<CrateManifest>,
+    pub fetched_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CrateManifest {
+    pub name: String,
+    pub version: String,
+    /// The dependencies on crates from other tracked repositories.
+    pub dependencies: Vec<CrateDependency>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CrateDependency {
+    pub name: String,
+    /// The version requirement from `Cargo.toml`.
+    pub requirement: String,
+    /// The version `Cargo.lock` resolved the requirement to, if the
+    /// repository has a lockfile.
+    pub resolved: Option<String>,
+}
+
+#[async_trait]
+impl Collection for RepositoryManifest {
+    type PrimaryKey = String;
+
+    fn collection_name() -> CollectionName {
+        CollectionName::new("khonsulabs", "repository-manifests")
+    }
+
+    fn define_views(_schema: &mut Schematic) -> Result<(), bonsaidb::core::Error> {
+        Ok(())
+    }
+}
+
+impl SerializedCollection for RepositoryManifest {
+    type Contents = Self;
+    type Format = Json;
+
+    fn format() -> Self::Format {
+        Json::default()
+    }
+}
+
+/// Where repository files are read from.
+#[async_trait]
+pub trait ManifestSource: Send + Sync {
+    /// Returns the contents of the file at `path` within `repository`
+    /// (`owner/name`), or None if it doesn't exist.
+    async fn read_file(&self, repository: &str, path: &str) -> anyhow::Result<Option<String>>;
+
+    /// Returns the names of the directories within `path` in `repository`.
+    async fn list_directories(&self, repository: &str, path: &str) -> anyhow::Result<Vec<String>>;
+}
+
+/// Reads files using GitHub's repository contents API.
+pub struct GitHubContents {
+    client: Client,
+    github: GitHubSection,
+}
+
+impl GitHubContents {
+    pub fn new(client: Client, github: GitHubSection) -> Self {
+        Self { client, github }
+    }
+
+    fn url(repository: &str, path: &str) -> String {
+        format!(
+            "https://api.github.com/repos/{}/contents/{}",
+            repository, path
+        )
+    }
+}
+
+#[derive(Deserialize)]
+struct ContentsEntry {
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+#[async_trait]
+impl ManifestSource for GitHubContents {
+    async fn read_file(&self, repository: &str, path: &str) -> anyhow::Result<Option<String>> {
+        let response = github_request(
+            &self.client,
+            &self.github,
+            &Self::url(repository, path),
+            "application/vnd.github.v3.raw",
+        )
+        .send()
+        .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Ok(Some(response.error_for_status()?.text().await?))
+    }
+
+    async fn list_directories(&self, repository: &str, path: &str) -> anyhow::Result<Vec<String>> {
+        let response = github_request(
+            &self.client,
+            &self.github,
+            &Self::url(repository, path),
+            "application/vnd.github.v3+json",
+        )
+        .send()
+        .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(Vec::new());
+        }
+        let entries = serde_json::from_str::<Vec<ContentsEntry>>(
+            &response.error_for_status()?.text().await?,
+        )?;
+        Ok(entries
+            .into_iter()
+            .filter(|entry| entry.kind == "dir")
+            .map(|entry| entry.name)
+            .collect())
+    }
+}
+
+/// Reads files from local checkouts, where each repository is checked out
+/// into a directory named after the repository within `root`.
+pub struct LocalCheckouts {
+    root: PathBuf,
+}
+
+impl LocalCheckouts {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    fn path(&self, repository: &str, path: &str) -> PathBuf {
+        let name = repository.rsplit('/').next().unwrap_or(repository);
+        self.root.join(name).join(path)
+    }
+}
+
+#[async_trait]
+impl ManifestSource for LocalCheckouts {
+    async fn read_file(&self, repository: &str, path: &str) -> anyhow::Result<Option<String>> {
+        match tokio::fs::read_to_string(self.path(repository, path)).await {
+            Ok(contents) => Ok(Some(contents)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn list_directories(&self, repository: &str, path: &str) -> anyhow::Result<Vec<String>> {
+        let mut directories = Vec::new();
+        let mut entries = match tokio::fs::read_dir(self.path(repository, path)).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(directories),
+            Err(err) => return Err(err.into()),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_type().await?.is_dir() {
+                directories.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        Ok(directories)
+    }
+}
+
+/// A crate read from a manifest before dependencies are filtered to our own
+/// crates.
+struct ParsedCrate {
+    name: String,
+    version: String,
+    /// Dependency names mapped to their version requirements.
+    dependencies: BTreeMap<String, String>,
+}
+
+async fn read_repository(
+    source: &dyn ManifestSource,
+    repository: &str,
+) -> anyhow::Result<(Vec<ParsedCrate>, HashMap<String, Vec<Version>>)> {
+    let mut crates = Vec::new();
+    let root = match source.read_file(repository, "Cargo.toml").await? {
+        Some(root) => root.parse::<Value>()?,
+        None => return Ok((crates, HashMap::new())),
+    };
+    let workspace = root.get("workspace").cloned();
+    if let Some(parsed) = parse_crate(&root, workspace.as_ref()) {
+        crates.push(parsed);
+    }
+
+    let members = workspace
+        .as_ref()
+        .and_then(|workspace| workspace.get("members"))
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+    for member in members.iter().filter_map(Value::as_str) {
+        let member_paths = if let Some(parent) = member.strip_suffix("/*") {
+            source
+                .list_directories(repository, parent)
+                .await?
+                .into_iter()
+                .map(|directory| format!("{}/{}", parent, directory))
+                .collect()
+        } else {
+            vec![member.to_string()]
+        };
+        for path in member_paths {
+            if let Some(manifest) = source
+                .read_file(repository, &format!("{}/Cargo.toml", path))
+                .await?
+            {
+                if let Some(parsed) = parse_crate(&manifest.parse::<Value>()?, workspace.as_ref()) {
+                    crates.push(parsed);
+                }
+            }
+        }
+    }
+
+    let mut locked = HashMap::<String, Vec<Version>>::new();
+    if let Some(lockfile) = source.read_file(repository, "Cargo.lock").await? {
+        let lockfile = lockfile.parse::<Value>()?;
+        for package in lockfile
+            .get("package")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            if let (Some(name), Some(Ok(version))) = (
+                package.get("name").and_then(Value::as_str),
+                package
+                    .get("version")
+                    .and_then(Value::as_str)
+                    .map(Version::parse),
+            ) {
+                locked.entry(name.to_string()).or_default().push(version);
+            }
+        }
+    }
+
+    Ok((crates, locked))
+}
+
+/// Reads the name, version and dependencies from a crate's manifest.
+/// Versions and dependencies inherited with `workspace = true` are looked up
+/// in `workspace`, the `[workspace]` table of the repository's root
+/// manifest. Returns None if the manifest has no package or its version
+/// can't be determined.
+fn parse_crate(manifest: &Value, workspace: Option<&Value>) -> Option<ParsedCrate> {
+    let package = manifest.get("package")?;
+    let name = package.get("name")?.as_str()?.to_string();
+    let version = match package.get("version")? {
+        Value::String(version) => version.clone(),
+        version if inherits(version) => workspace?
+            .get("package")?
+            .get("version")?
+            .as_str()?
+            .to_string(),
+        _ => return None,
+    };
+    let workspace_dependencies = workspace.and_then(|workspace| workspace.get("dependencies"));
+
+    // Platform-specific dependencies are listed in the same tables within
+    // `[target.'cfg(...)']`.
+    let mut sections = vec![manifest];
+    if let Some(targets) = manifest.get("target").and_then(Value::as_table) {
+        sections.extend(targets.values());
+    }
+    let mut dependencies = BTreeMap::new();
+    for section in sections {
+        for table in ["dependencies", "dev-dependencies", "build-dependencies"] {
+            let table = match section.get(table).and_then(Value::as_table) {
+                Some(table) => table,
+                None => continue,
+            };
+            for (key, specification) in table {
+                let specification = if inherits(specification) {
+                    match workspace_dependencies.and_then(|dependencies| dependencies.get(key)) {
+                        Some(specification) => specification,
+                        None => continue,
+                    }
+                } else {
+                    specification
+                };
+                let (dependency, requirement) = match specification {
+                    Value::String(requirement) => (key.clone(), requirement.clone()),
+                    Value::Table(table) => (
+                        table
+                            .get("package")
+                            .and_then(Value::as_str)
+                            .unwrap_or(key)
+                            .to_string(),
+                        table
+                            .get("version")
+                            .and_then(Value::as_str)
+                            .unwrap_or("*")
+                            .to_string(),
+                    ),
+                    _ => continue,
+                };
+                dependencies.entry(dependency).or_insert(requirement);
+            }
+        }
+    }
+
+    Some(ParsedCrate {
+        name,
+        version,
+        dependencies,
+    })
+}
+
+/// Returns true if `value` is inherited from the workspace, written as
+/// `{ workspace = true }`.
+fn inherits(value: &Value) -> bool {
+    value.get("workspace").and_then(Value::as_bool) == Some(true)
+}
+
+/// Reads the manifests of every project in the catalog from `source` and
+/// stores which crates depend on crates from other repositories.
+pub async fn update_manifests<C: AsyncConnection>(
+    database: &C,
+    source: &dyn ManifestSource,
+) -> anyhow::Result<()> {
+    let mut repositories = Vec::new();
+    // Crates from repositories that couldn't be read are taken from the last
+    // stored manifest so that dependencies on them are still recognized.
+    let mut unread_crates = Vec::new();
+    for (key, project) in projects::catalog(database).await? {
+        let repository = match project.repository_name() {
+            Some(repository) => repository,
+            None => continue,
+        };
+        match read_repository(source, &repository).await {
+            Ok((crates, locked)) => repositories.push((key, repository, crates, locked)),
+            Err(err) => {
+                tracing::error!("Error reading manifests for {}: {}", repository, err);
+                if let Some(stored) = RepositoryManifest::get_async(key, database).await? {
+                    unread_crates.extend(
+                        stored
+                            .contents
+                            .crates
+                            .into_iter()
+                            .map(|parsed| (parsed.name, repository.clone())),
+                    );
+                }
+            }
+        }
+    }
+
+    let crate_repositories = repositories
+        .iter()
+        .flat_map(|(_, repository, crates, _)| {
+            crates
+                .iter()
+                .map(move |parsed| (parsed.name.as_str(), repository.as_str()))
+        })
+        .chain(
+            unread_crates
+                .iter()
+                .map(|(name, repository)| (name.as_str(), repository.as_str())),
+        )
+        .collect::<HashMap<_, _>>();
+
+    for (key, repository, crates, locked) in &repositories {
+        let manifest = RepositoryManifest {
+            repository: repository.clone(),
+            crates: crates
+                .iter()
+                .map(|parsed| CrateManifest {
+                    name: parsed.name.clone(),
+                    version: parsed.version.clone(),
+                    dependencies: parsed
+                        .dependencies
+                        .iter()
+                        .filter(|(name, _)| {
+                            crate_repositories
+                                .get(name.as_str())
+                                .is_some_and(|other| other != repository)
+                        })
+                        .map(|(name, requirement)| CrateDependency {
+                            name: name.clone(),
+                            requirement: requirement.clone(),
+                            resolved: resolve(locked, name, requirement),
+                        })
+                        .collect(),
+                })
+                .collect(),
+            fetched_at: Utc::now(),
+        };
+        RepositoryManifest::overwrite_async(key, manifest, database)
+            .await
+            .map_err(|err| err.error)?;
+    }
+    tracing::info!("Updated manifests for {} repositories", repositories.len());
+
+    Ok(())
+}
+
+/// Returns the locked version of `name` that satisfies `requirement`.
+fn resolve(
+    locked: &HashMap<String, Vec<Version>>,
+    name: &str,
+    requirement: &str,
+) -> Option<String> {
+    let versions = locked.get(name)?;
+    let requirement = VersionReq::parse(requirement).ok();
+    versions
+        .iter()
+        .filter(|version| {
+            requirement
+                .as_ref()
+                .is_none_or(|requirement| requirement.matches(version))
+        })
+        .max()
+        .map(Version::to_string)
+}
+
+/// Updates the manifests from GitHub every six hours.
+pub async fn update_manifests_periodically<C: AsyncConnection + AsyncPubSub>(
+    database: C,
+    github: GitHubSection,
+    shutdown: Shutdown,
+) -> anyhow::Result<()> {
+    let source = GitHubContents::new(Client::new(), github);
+    loop {
+        tracing::info!("Fetching Cargo manifests from GitHub");
+        match update_manifests(&database, &source).await {
+            Ok(()) => cache::invalidate(&database).await?,
+            Err(err) => tracing::error!("Error updating manifests: {}", err),
+        }
+        if shutdown.sleep(Duration::from_secs(6 * 60 * 60)).await {
+            return Ok(());
+        }
+    }
+}
+
+/// One side of a dependency between two projects' crates.
+#[derive(Serialize, Debug)]
+pub struct CrateUse {
+    /// The key of the project on the other side of the dependency.
+    pub project: String,
+    /// The crate that has the dependency.
+    pub dependent: String,
+    /// The crate being depended upon.
+    pub dependency: String,
+    pub requirement: String,
+    pub resolved: Option<String>,
+    /// The version of `dependency` in its repository's manifest.
+    pub latest: String,
+    /// True if `requirement` doesn't allow `latest`.
+    pub outdated: bool,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct ProjectCrates {
+    /// Crates from other projects that this project's crates depend on.
+    pub uses: Vec<CrateUse>,
+    /// Crates from other projects that depend on this project's crates.
+    pub used_by: Vec<CrateUse>,
+}
+
+/// Returns the crate dependencies between projects, keyed by project key.
+pub async fn crate_graph<C: AsyncConnection>(
+    database: &C,
+) -> anyhow::Result<HashMap<String, ProjectCrates>> {
+    let manifests = RepositoryManifest::all_async(database).await?;
+    let owners =
+        manifests
+            .iter()
+            .flat_map(|manifest| {
+                manifest.contents.crates.iter().map(move |parsed| {
+                    (parsed.name.as_str(), (manifest.header.id.as_str(), parsed))
+                })
+            })
+            .collect::<HashMap<_, _>>();
+
+    let mut graph = HashMap::<String, ProjectCrates>::new();
+    let mut seen = HashSet::new();
+    for manifest in &manifests {
+        for dependent in &manifest.contents.crates {
+            for dependency in &dependent.dependencies {
+                let (owner, dependency_manifest) = match owners.get(dependency.name.as_str()) {
+                    Some(owner) => *owner,
+                    None => continue,
+                };
+                if !seen.insert((dependent.name.as_str(), dependency.name.as_str())) {
+                    continue;
+                }
+
+                let outdated = match (
+                    VersionReq::parse(&dependency.requirement),
+                    Version::parse(&dependency_manifest.version),
+                ) {
+                    (Ok(requirement), Ok(latest)) => !requirement.matches(&latest),
+                    _ => false,
+                };
+                let crate_use = |project: &str| CrateUse {
+                    project: project.to_string(),
+                    dependent: dependent.name.clone(),
+                    dependency: dependency.name.clone(),
+                    requirement: dependency.requirement.clone(),
+                    resolved: dependency.resolved.clone(),
+                    latest: dependency_manifest.version.clone(),
+                    outdated,
+                };
+                graph
+                    .entry(manifest.header.id.clone())
+                    .or_default()
+                    .uses
+                    .push(crate_use(owner));
+                graph
+                    .entry(owner.to_string())
+                    .or_default()
+                    .used_by
+                    .push(crate_use(&manifest.header.id));
+            }
+        }
+    }
+
+    Ok(graph)
+}
+
+/// Commands for the crate manifests of tracked repositories.
+#[derive(StructOpt, Debug)]
+pub enum ManifestCommand {
+    /// Reads every project's Cargo manifests and stores their dependencies.
+    Update {
+        /// Read manifests from local checkouts within this directory instead
+        /// of from GitHub. Each repository must be checked out into a
+        /// directory with the repository's name.
+        #[structopt(long, parse(from_os_str))]
+        checkouts: Option<PathBuf>,
+    },
+    /// Prints the dependencies between projects' crates.
+    Show,
+}
+
+impl ManifestCommand {
+    pub async fn execute<C: AsyncConnection + AsyncPubSub>(
+        self,
+        database: &C,
+        github: &GitHubSection,
+    ) -> anyhow::Result<()> {
+        match self {
+            ManifestCommand::Update { checkouts } => {
+                match checkouts {
+                    Some(root) => update_manifests(database, &LocalCheckouts::new(root)).await?,
+                    None => {
+                        let source = GitHubContents::new(Client::new(), github.clone());
+                        update_manifests(database, &source).await?
+                    }
+                }
+                cache::invalidate(database).await
+            }
+            ManifestCommand::Show => {
+                let mut graph = crate_graph(database).await?.into_iter().collect::<Vec<_>>();
+                graph.sort_by(|a, b| a.0.cmp(&b.0));
+                for (project, crates) in graph {
+                    for crate_use in &crates.uses {
+                        println!(
+                            "{}: {} depends on {} {} (latest {}){}",
+                            project,
+                            crate_use.dependent,
+                            crate_use.dependency,
+                            crate_use.requirement,
+                            crate_use.latest,
+                            if crate_use.outdated { " OUTDATED" } else { "" }
+                        );
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn toml(text: &str) -> Value {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn crates_inherit_from_their_workspace() {
+        let root = toml(
+            r#"
+            [workspace]
+            members = ["crates/*"]
+
+            [workspace.package]
+            version = "0.4.0"
+
+            [workspace.dependencies]
+            bonsaidb-core = { version = "0.4.0", path = "crates/bonsaidb-core" }
+            "#,
+        );
+        let member = toml(
+            r#"
+            [package]
+            name = "bonsaidb-local"
+            version.workspace = true
+
+            [dependencies]
+            bonsaidb-core = { workspace = true }
+            nebari = "0.5"
+            sled = { package = "nebari-sled", version = "0.2.1" }
+            bonsaidb-utils = { path = "../bonsaidb-utils" }
+
+            [target.'cfg(unix)'.dependencies]
+            tokio-uring = "0.3"
+
+            [dev-dependencies]
+            nebari = "0.1"
+            "#,
+        );
+
+        let parsed = parse_crate(&member, root.get("workspace")).unwrap();
+        assert_eq!(parsed.name, "bonsaidb-local");
+        assert_eq!(parsed.version, "0.4.0");
+        assert_eq!(
+            parsed
+                .dependencies
+                .iter()
+                .map(|(name, requirement)| (name.as_str(), requirement.as_str()))
+                .collect::<Vec<_>>(),
+            [
+                ("bonsaidb-core", "0.4.0"),
+                ("bonsaidb-utils", "*"),
+                ("nebari", "0.5"),
+                ("nebari-sled", "0.2.1"),
+                ("tokio-uring", "0.3"),
+            ]
+        );
+
+        // Without the workspace, the version can't be determined.
+        assert!(parse_crate(&member, None).is_none());
+        assert!(parse_crate(&toml("[workspace]\nmembers = []"), None).is_none());
+    }
+
+    #[test]
+    fn requirements_resolve_to_the_newest_locked_version() {
+        let locked = HashMap::from([(
+            String::from("nebari"),
+            vec![
+                Version::parse("0.1.3").unwrap(),
+                Version::parse("0.5.1").unwrap(),
+                Version::parse("0.5.3").unwrap(),
+            ],
+        )]);
+        assert_eq!(resolve(&locked, "nebari", "0.5").as_deref(), Some("0.5.3"));
+        assert_eq!(
+            resolve(&locked, "nebari", "=0.1.3").as_deref(),
+            Some("0.1.3")
+        );
+        // An outdated requirement that nothing locked satisfies.
+        assert_eq!(resolve(&locked, "nebari", "0.2"), None);
+        assert_eq!(resolve(&locked, "sled", "0.34"), None);
+    }
+}
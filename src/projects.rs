@@ -1,211 +1,474 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::OsStr,
+    fmt::{self, Display},
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use once_cell::sync::Lazy;
+use async_trait::async_trait;
+use bonsaidb::core::{
+    connection::AsyncConnection,
+    pubsub::AsyncPubSub,
+    schema::{Collection, CollectionName, Qualified, Schematic, SerializedCollection},
+    transaction::{Operation, Transaction},
+};
+use chrono::{DateTime, Utc};
+use notify::{RecursiveMode, Watcher};
 use serde::{Deserialize, Serialize};
+use structopt::StructOpt;
+use tokio::sync::mpsc;
+use transmog_json::{serde_json, Json};
+
+use crate::{cache, html, schema::GitHubLatestActivity, shutdown::Shutdown};
+
+/// The directory the project catalog is loaded from.
+pub const CATALOG_DIRECTORY: &str = "projects";
 
-#[derive(Serialize, Deserialize, Debug)]
+/// A project in the catalog. The catalog is loaded from the files in
+/// [`CATALOG_DIRECTORY`] and stored in the `Projects` schema, using the
+/// lowercase repository name as the primary key.
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Project {
-    pub name: &'static str,
-    pub tagline: &'static str,
-    pub description: &'static str,
-    pub homepage: Option<&'static str>,
-    pub repository: &'static str,
-    pub documentation: Option<&'static str>,
-}
-
-const ACTIONABLE: Project = Project {
-    name: "actionable",
-    tagline: "An enum-based async framework for building permission-driven APIs",
-    description: r#"<p>Actionable defines a basic Role Based Access Control permissions framework as well as a set of traits that allow "dispatching" an enum. Additionally, it offers procedural macros to remove some common sources of code duplication."#,
-    homepage: None,
-    repository: "https://github.com/khonsulabs/actionable",
-    documentation: Some("https://khonsulabs.github.io/actionable/main/actionable/"),
-};
+    pub name: String,
+    pub tagline: String,
+    /// The sanitized HTML rendered from the file's Markdown description.
+    pub description: String,
+    pub homepage: Option<String>,
+    pub repository: String,
+    pub documentation: Option<String>,
+    /// The keys of projects this project is built upon.
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    /// The keys of projects this project was created to support, when it
+    /// isn't already listed as a dependency of those projects.
+    #[serde(default)]
+    pub built_for: Vec<String>,
+    /// The keys of projects this project replaces.
+    #[serde(default)]
+    pub supersedes: Vec<String>,
+    #[serde(default)]
+    pub status: ProjectStatus,
+    /// The key of the project whose progress this project is waiting on,
+    /// which explains why it may not have seen recent activity.
+    #[serde(default)]
+    pub waiting_on: Option<String>,
+}
 
-const BONSAIDB: Project = Project {
-    name: "BonsaiDb",
-    tagline: "A document database that grows with you.",
-    description: r#"
-        We evaluated the landscape of pure-Rust database implementations, and none fit our goals for an eventual architecture that scaled the way we wanted. Additionally, the non-Rust standards are difficult to deploy in a highly-available fashion.
-    "#,
-    homepage: Some("https://bonsaidb.io/"),
-    repository: "https://github.com/khonsulabs/bonsaidb",
-    documentation: Some("https://docs.rs/bonsaidb"),
-};
+impl Project {
+    /// Returns the full name of the repository (`owner/name`), if it is hosted
+    /// on GitHub.
+    pub fn repository_name(&self) -> Option<String> {
+        let path = self.repository.strip_prefix("https://github.com/")?;
+        let mut parts = path.trim_end_matches('/').split('/');
+        let owner = parts.next()?;
+        let name = parts.next()?;
+        Some(format!("{}/{}", owner, name))
+    }
 
-const CUSTODIAN: Project = Project {
-    name: "custodian",
-    tagline: "End-user secret management with Rust. ",
-    description: r#"
-        <p>Custodian aims to be a general purpose set of secret management APIs aimed at helping developers store secrets easily, yet securely.
-        <p>Currently, the only crate published is custodian-password, an easy-to-use OPAQUE-KE wrapper that BonsaiDb uses when setting a password for a user. This key exchange protocol ensures that the password never leaves the client, and the server can still verify upon a new connection that the original password was provided.
-    "#,
-    homepage: None,
-    repository: "https://github.com/khonsulabs/custodian",
-    documentation: Some("https://khonsulabs.github.io/custodian/main/custodian_password/"),
-};
+    /// Returns each relationship this project declares, paired with the key
+    /// of the related project.
+    pub fn relationships(&self) -> impl Iterator<Item = (Relationship, &str)> + '_ {
+        self.depends_on
+            .iter()
+            .map(|key| (Relationship::DependsOn, key.as_str()))
+            .chain(
+                self.built_for
+                    .iter()
+                    .map(|key| (Relationship::BuiltFor, key.as_str())),
+            )
+            .chain(
+                self.supersedes
+                    .iter()
+                    .map(|key| (Relationship::Supersedes, key.as_str())),
+            )
+    }
+}
 
-const EASYGPU: Project = Project {
-    name: "easygpu",
-    tagline: "wgpu made a little easier",
-    description: r#"
-        <p>Kludgine transitioned between multiple rendering backends before utlimately easygpu was developed as an offshoot 
-        from a large refactoring of <a href="https://github.com/cloudhead/rgx>rgx</a> aimed at bringing compatibility to wgpu 0.6 at the time.
-        <p>easygpu is not aiming to be a major entry in the ecosystem of Rust graphics. It provides a semi-stable base layer that Kludgine builds upon that is slightly easier to consume than wgpu directly. Additionally, this repository contains easygpu-lyon, which provides 2d tesselated shape/path drawing in a resuable pipeline, which Kludgine uses to provide its shape rendering.
-    "#,
-    homepage: None,
-    repository: "https://github.com/khonsulabs/easygpu",
-    documentation: None,
-};
+/// Where a project is in its lifecycle.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProjectStatus {
+    /// Early development, where large changes are expected.
+    Experimental,
+    /// Under active development.
+    #[default]
+    Active,
+    /// Feature-complete, and only updated as needed.
+    Maintenance,
+    /// No longer developed.
+    Archived,
+}
 
-const ENGLISHID: Project = Project {
-    name: "englishid",
-    tagline: "Encode and decode data using plain English.",
-    description: r#"<p>For Ncog, we had two problems that utilized random data that sometimes might need to be read aloud: invite codes and backup keys. This crate encodes data using an English wordlist with 13 bits of information being represented by each word. For Ncog, this means that invite codes can be 4 words long, and Ed25519 backup keys can be 20 words long."#,
-    homepage: None,
-    repository: "https://github.com/khonsulabs/englishid",
-    documentation: Some("https://khonsulabs.github.io/englishid/main/englishid/"),
-};
+impl ProjectStatus {
+    /// Returns true if a lack of recent activity is worth explaining.
+    pub fn expects_activity(self) -> bool {
+        matches!(self, ProjectStatus::Experimental | ProjectStatus::Active)
+    }
+}
 
-const FABRUIC: Project = Project {
-    name: "fabruic",
-    tagline: "An easy-to-use QUIC-based protocol that supports reliable, ordered payload delivery.",
-    description: r#"<p>We needed a reliable protocol for BonsaiDb, and QUIC is a great general-purpose networking protocol that solves many issues that TCP connections suffer from. Fabruic will eventaully grow from only supporting QUIC to being transparently multi-protocol, which will enable WebRTC communications with a web browser. This will replace WebSockets as the best path for WASM BonsaiDb clients. Additionally, Fabruic will add support for unordered, unreliable datagram delivery in addition to the current ordered, reliable streams."#,
-    homepage: None,
-    repository: "https://github.com/khonsulabs/fabruic",
-    documentation: Some("https://khonsulabs.github.io/fabruic/main/fabruic/"),
-};
+impl Display for ProjectStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ProjectStatus::Experimental => "experimental",
+            ProjectStatus::Active => "active",
+            ProjectStatus::Maintenance => "maintenance",
+            ProjectStatus::Archived => "archived",
+        })
+    }
+}
 
-const FIGURES: Project = Project {
-    name: "figures",
-    tagline: "A math library specialized for 2d screen graphics. ",
-    description: r#"
-        <p>Rust has a vibrant ecosystem for 2d math APIs. Gooey, Kludgine, and easygpu were all using Euclid, but some of the opinionated decisions for their API did not agree with our desires in the types exposed through Gooey.
-        <p>Figures takes a similar approach to Euclid by associating a "unit" type with the primitive numerical type. Additionally, it adds the concept of DisplayScale, allowing for logic to be built that can convert between three units of measurement: raw pixels, dpi-scaled pixels, and user-scaled pixels. Gooey and Kludgine use this to enable automatically scaling based on the screen's DPI settings, but also allowing an additonal scaling factor to be set on-top of the DPI-corrected scaling.
-    "#,
-    homepage: None,
-    repository: "https://github.com/khonsulabs/figures",
-    documentation: Some("https://khonsulabs.github.io/figures/main/figures/"),
-};
+/// How one project relates to another.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Relationship {
+    DependsOn,
+    BuiltFor,
+    Supersedes,
+}
 
-const GOOEY: Project = Project {
-    name: "gooey",
-    tagline: "An experimental cross-platform graphical user interface (GUI).",
-    description: r#"
-        <p>We believe in having native applications, but we also believe that having your app or game be accessible inside of a web browser makes it much more approachable for a potential new user.
-        <p>The GUI ecosystem in Rust is rapidly evolving, but we had our own opinions on how to best architect a GUI framework in a Rust-y fashion. Gooey is our attempt at that: a cross-platform API that can run natively inside of any wgpu application or inside of a web-browser, being translated to native DOM elements in the process.
-    "#,
-    homepage: None,
-    repository: "https://github.com/khonsulabs/gooey",
-    documentation: Some("https://gooey.rs/main/gooey/"),
-};
+#[async_trait]
+impl Collection for Project {
+    type PrimaryKey = String;
 
-const GOOEY_CANVAS: Project = Project {
-    name: "gooey-canvas",
-    tagline: "A Canvas widget for the `Gooey` UI framework ",
-    description: r#"<p>The Canvas widget adds the ability to create cross-platform 2d drawing code using the Renderer trait -- the same trait that Gooey uses to rasterize its widgets on the native frontend."#,
-    homepage: None,
-    repository: "https://github.com/khonsulabs/gooey-canvas",
-    documentation: None,
-};
+    fn collection_name() -> CollectionName {
+        CollectionName::new("khonsulabs", "projects")
+    }
 
-const KLUDGINE: Project = Project {
-    name: "Kludgine",
-    tagline: "2D graphics and windowing built atop wgpu",
-    description: r#"
-        <p>Deep down our passion is still with games, even though we may be focusing a lot of general-purpose application development at the moment. Kludgine was born after evaluating other libraries at the time and deciding there was still room for improvement.
-
-        <p>Kludgine is the base layer for Gooey, our Graphical User Interface crate.
-    "#,
-    homepage: None,
-    repository: "https://github.com/khonsulabs/nebari",
-    documentation: Some("https://nebari.bonsaidb.io/main/nebari"),
-};
+    fn define_views(_schema: &mut Schematic) -> Result<(), bonsaidb::core::Error> {
+        Ok(())
+    }
+}
 
-const NCOG: Project = Project {
-    name: "Ncog",
-    tagline:
-        "A self-hostable collaboration platform built with privacy and data independence in mind.",
-    description: r#"
-        <p>Our original goal with Khonsu Labs is to build an MMORPG, but at the core of our desires of what an MMORPG should contain, we believed reliable, persistent private and group messaging was important. We also went as far as to believe that if we were going to build or own chat layer instead of integrate with another solution, we should only do so if we felt like we could bring something unique to the table. We believe we can.
-        <p>Most solutions to this problem are very difficult to deploy and maintain. Our aim is to design a suite of communication and collaboration services that come with sensible, secure default settings and are easy to customize, deploy, and eventually scale.
-        <p>Ncog will be the first large-scale project that unifies Gooey and BonsaiDb.
-    "#,
-    homepage: None,
-    repository: "https://github.com/khonsulabs/ncog",
-    documentation: None,
-};
+impl SerializedCollection for Project {
+    type Contents = Self;
+    type Format = Json;
 
-const NEBARI: Project = Project {
-    name: "Nebari",
-    tagline: "ACID-compliant key-value database implementation using an append-only file format.",
-    description: r#"
-        <p>While we started BonsaiDb atop another storage layer, we decided to pursue an in-house implementation that we could tailor-fit to the needs of BonsaiDb.
-
-        <p>Nebari aims to provide speed, safety, and reliability while still remaining easy to understand and approachable to new contributors.
-    "#,
-    homepage: None,
-    repository: "https://github.com/khonsulabs/nebari",
-    documentation: Some("https://nebari.bonsaidb.io/main/nebari"),
-};
+    fn format() -> Self::Format {
+        Json::default()
+    }
+}
 
-const POT: Project = Project {
-    name: "pot",
-    tagline: "An experimental self-describing binary format written in Rust for Serde",
-    description: r#"
-        <p>BonsaiDb originally used CBOR to encode documents before we wrote our own format. Pot is very similar to CBOR, but it aims to improve in one way: not repeating "identifiers" multiple times. When encoding an array of structures with CBOR that has a propery named "disconnected", the word "disconnected" will show up one for each entry in the array. With Pot, the word disconnected will show up only once. 
-        
-        <p>This space savings comes at a slight cost, but may yield less overall packets of data being sent. Additionally, Pot has session-based capabilities that further reduce the amount of data needed for network communications.
-    "#,
-    homepage: None,
-    repository: "https://github.com/khonsulabs/pot",
-    documentation: Some("https://pot.bonsaidb.io/main/pot/"),
-};
+/// Returns the catalog, keyed by the lowercase repository name.
+pub async fn catalog<C: AsyncConnection>(
+    database: &C,
+) -> anyhow::Result<BTreeMap<String, Project>> {
+    Ok(Project::all_async(database)
+        .await?
+        .into_iter()
+        .map(|project| (project.header.id, project.contents))
+        .collect())
+}
 
-const PROJECTS_PROJECT: Project = Project {
-    name: "projects",
-    tagline: "The website you're accessing.",
-    description: r#"
-        <p>The website you are browsing is served via the Axum framework. The GitHub history is being retrieved from a local BonsaiDb database, which is updated in the background periodically. The goal of this website is to try to help tie together all of the work that we are doing into one location. Because we are only a few developers with such a large set of repositories, it will likely be that some crates go periods of time without updates.
-        <p>This website should help any consumers of Khonsu Labs' crates to see that each crate plays an important role in our ecosystem. As long as we're working towards our big-picture goals, each one of our crates is crucial to the success of our goals.
-        <p>A fun note: This project will likely be BonsaiDb's longest-running production application.
-    "#,
-    homepage: None,
-    repository: "https://github.com/khonsulabs/projects",
-    documentation: None,
-};
+/// A project's status along with how recently its repository was active.
+#[derive(Serialize, Debug)]
+pub struct Lifecycle {
+    pub status: ProjectStatus,
+    /// The date of the most recent event in the project's repository.
+    pub last_activity: Option<String>,
+    /// The number of days since the most recent event.
+    pub days_inactive: Option<i64>,
+    /// True if the project is expected to be active but hasn't been for
+    /// [`STALE_AFTER_DAYS`].
+    pub stale: bool,
+    pub waiting_on: Option<String>,
+}
 
-const STYLECS: Project = Project {
-    name: "stylecs",
-    tagline: "A style component system for Rust",
-    description: r#"
-        <p>This small crate provides a basic set of abstractions for building "style" information. It is what Gooey uses to implement its styling and theming.
-        <p>This crate is general-purpose enough it likely is not useful for many people outside of the context of Gooey or building your own style-based data structures.
-    "#,
-    homepage: None,
-    repository: "https://github.com/khonsulabs/stylecs",
-    documentation: Some("https://khonsulabs.github.io/stylecs/main/stylecs/"),
-};
+/// The number of days without activity before an experimental or active
+/// project is considered stale.
+pub const STALE_AFTER_DAYS: i64 = 60;
 
-pub static PROJECTS: Lazy<HashMap<String, Project>> = Lazy::new(|| {
-    [
-        ACTIONABLE,
-        BONSAIDB,
-        CUSTODIAN,
-        EASYGPU,
-        ENGLISHID,
-        FABRUIC,
-        FIGURES,
-        GOOEY,
-        GOOEY_CANVAS,
-        KLUDGINE,
-        NCOG,
-        NEBARI,
-        POT,
-        PROJECTS_PROJECT,
-        STYLECS,
-    ]
-    .into_iter()
-    .map(|project| (project.name.to_ascii_lowercase(), project))
-    .collect()
-});
+/// Returns the time of the most recent event in each repository, keyed by
+/// `owner/name`.
+pub async fn last_activity<C: AsyncConnection>(
+    database: &C,
+) -> anyhow::Result<HashMap<String, DateTime<Utc>>> {
+    Ok(database
+        .view::<GitHubLatestActivity>()
+        .reduce_grouped()
+        .await?
+        .into_iter()
+        .map(|latest| (latest.key, DateTime::<Utc>::from(latest.value)))
+        .collect())
+}
+
+/// Returns the lifecycle of each project in `catalog`.
+pub async fn lifecycles<C: AsyncConnection>(
+    database: &C,
+    catalog: &BTreeMap<String, Project>,
+) -> anyhow::Result<BTreeMap<String, Lifecycle>> {
+    let now = Utc::now();
+    let latest = last_activity(database).await?;
+    let mut lifecycles = BTreeMap::new();
+    for (key, project) in catalog {
+        let last_activity = project
+            .repository_name()
+            .and_then(|repository| latest.get(&repository).copied());
+        let days_inactive = last_activity.map(|last_activity| (now - last_activity).num_days());
+        lifecycles.insert(
+            key.clone(),
+            Lifecycle {
+                status: project.status,
+                last_activity: last_activity
+                    .map(|last_activity| last_activity.format("%B %e, %Y").to_string()),
+                days_inactive,
+                stale: project.status.expects_activity()
+                    && days_inactive.is_none_or(|days| days >= STALE_AFTER_DAYS),
+                waiting_on: project.waiting_on.clone(),
+            },
+        );
+    }
+    Ok(lifecycles)
+}
+
+/// A file read from the catalog directory.
+pub struct ProjectFile {
+    pub path: PathBuf,
+    /// The file's name without the extension, in lowercase.
+    pub key: String,
+    /// The file's contents, or an empty string if it couldn't be read.
+    pub contents: String,
+    pub project: anyhow::Result<Project>,
+}
+
+/// Reads and parses every `*.md` file in `directory`, sorted by path. Each
+/// file starts with TOML frontmatter between `+++` lines, followed by the
+/// project's description in Markdown.
+pub fn read_project_files(directory: &Path) -> anyhow::Result<Vec<ProjectFile>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.extension().and_then(OsStr::to_str) != Some("md") {
+            continue;
+        }
+
+        let key = path
+            .file_stem()
+            .and_then(OsStr::to_str)
+            .map(str::to_ascii_lowercase)
+            .unwrap_or_default();
+        let (contents, project) = match std::fs::read_to_string(&path) {
+            Ok(contents) => {
+                let project = parse_project(&contents);
+                (contents, project)
+            }
+            Err(err) => (String::new(), Err(err.into())),
+        };
+        files.push(ProjectFile {
+            path,
+            key,
+            contents,
+            project,
+        });
+    }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+/// Reads every project file in `directory`. The file's name (without the
+/// extension) is the project's key.
+///
+/// If any file is malformed, or two files have the same key, an error
+/// describing every problem is returned.
+pub fn load_catalog(directory: &Path) -> anyhow::Result<BTreeMap<String, Project>> {
+    let mut catalog = BTreeMap::new();
+    let mut errors = Vec::new();
+    for file in read_project_files(directory)? {
+        match file.project {
+            Ok(project) => {
+                if catalog.insert(file.key.clone(), project).is_some() {
+                    errors.push(format!(
+                        "{}: duplicate project key `{}`",
+                        file.path.display(),
+                        file.key
+                    ));
+                }
+            }
+            Err(err) => errors.push(format!("{}: {}", file.path.display(), err)),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(catalog)
+    } else {
+        anyhow::bail!("invalid project files:\n{}", errors.join("\n"))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Frontmatter {
+    name: String,
+    tagline: String,
+    homepage: Option<String>,
+    repository: String,
+    documentation: Option<String>,
+    #[serde(default)]
+    depends_on: Vec<String>,
+    #[serde(default)]
+    built_for: Vec<String>,
+    #[serde(default)]
+    supersedes: Vec<String>,
+    #[serde(default)]
+    status: ProjectStatus,
+    waiting_on: Option<String>,
+}
+
+/// Splits a project file into its TOML frontmatter and its Markdown
+/// description.
+pub fn split_project_file(contents: &str) -> anyhow::Result<(&str, &str)> {
+    let contents = contents
+        .strip_prefix("+++")
+        .ok_or_else(|| anyhow::anyhow!("file must begin with `+++`"))?;
+    let (frontmatter, description) = contents
+        .split_once("\n+++")
+        .ok_or_else(|| anyhow::anyhow!("frontmatter is missing its closing `+++`"))?;
+    Ok((frontmatter, description.trim()))
+}
+
+fn parse_project(contents: &str) -> anyhow::Result<Project> {
+    let (frontmatter, description) = split_project_file(contents)?;
+    let frontmatter = toml::from_str::<Frontmatter>(frontmatter)?;
+    Ok(Project {
+        name: frontmatter.name,
+        tagline: frontmatter.tagline,
+        description: html::render_markdown(description),
+        homepage: frontmatter.homepage,
+        repository: frontmatter.repository,
+        documentation: frontmatter.documentation,
+        depends_on: frontmatter.depends_on,
+        built_for: frontmatter.built_for,
+        supersedes: frontmatter.supersedes,
+        status: frontmatter.status,
+        waiting_on: frontmatter.waiting_on,
+    })
+}
+
+/// Replaces the stored catalog with `catalog` in a single transaction.
+pub async fn store_catalog<C: AsyncConnection>(
+    database: &C,
+    catalog: &BTreeMap<String, Project>,
+) -> anyhow::Result<()> {
+    let mut transaction = Transaction::new();
+    for (key, project) in catalog {
+        transaction.push(Operation::overwrite_serialized::<Project>(
+            key.clone(),
+            project,
+        )?);
+    }
+    for existing in Project::all_async(database).await? {
+        if !catalog.contains_key(&existing.header.id) {
+            transaction.push(Operation::delete(
+                Project::collection_name(),
+                existing.header.try_into()?,
+            ));
+        }
+    }
+    database.apply_transaction(transaction).await?;
+    Ok(())
+}
+
+/// Loads the catalog from `directory` and stores it. If the files can't be
+/// loaded, the stored catalog is left unchanged.
+pub async fn reload_catalog<C: AsyncConnection>(
+    database: &C,
+    directory: &Path,
+) -> anyhow::Result<usize> {
+    let catalog = load_catalog(directory)?;
+    store_catalog(database, &catalog).await?;
+    Ok(catalog.len())
+}
+
+/// Reloads the catalog from `directory` now and each time a file within it
+/// changes, until shutdown is requested. Errors are logged, and the last
+/// catalog that loaded successfully remains in place.
+pub async fn watch_catalog<C: AsyncConnection + AsyncPubSub>(
+    database: C,
+    directory: PathBuf,
+    shutdown: Shutdown,
+) -> anyhow::Result<()> {
+    let (sender, mut changes) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event| {
+        let _ = sender.send(event);
+    })?;
+    watcher.watch(&directory, RecursiveMode::NonRecursive)?;
+
+    loop {
+        match reload_catalog(&database, &directory).await {
+            Ok(count) => {
+                tracing::info!("Loaded {} projects from {}", count, directory.display());
+                cache::invalidate(&database).await?;
+            }
+            Err(err) => tracing::error!("Error reloading project catalog: {}", err),
+        }
+
+        // Editors often write several events for a single save, so wait
+        // briefly and then discard any other pending events.
+        let change = tokio::select! {
+            change = changes.recv() => change,
+            _ = shutdown.clone().requested() => return Ok(()),
+        };
+        match change {
+            Some(Ok(_)) => {}
+            Some(Err(err)) => tracing::error!("Error watching project catalog: {}", err),
+            None => return Ok(()),
+        }
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        while changes.try_recv().is_ok() {}
+    }
+}
+
+/// Commands for viewing and reloading the project catalog.
+#[derive(StructOpt, Debug)]
+pub enum ProjectCommand {
+    /// Lists the projects in the catalog.
+    List,
+    /// Prints a project as JSON.
+    Show { key: String },
+    /// Loads the catalog from the `projects` directory, replacing the stored
+    /// catalog.
+    Reload,
+}
+
+impl ProjectCommand {
+    pub async fn execute<C: AsyncConnection + AsyncPubSub>(
+        self,
+        database: &C,
+    ) -> anyhow::Result<()> {
+        match self {
+            ProjectCommand::List => {
+                let catalog = catalog(database).await?;
+                let lifecycles = lifecycles(database, &catalog).await?;
+                for (key, project) in &catalog {
+                    let lifecycle = &lifecycles[key];
+                    print!(
+                        "{}: {} - {} [{}",
+                        key, project.name, project.tagline, lifecycle.status
+                    );
+                    if let Some(last_activity) = &lifecycle.last_activity {
+                        print!(", last active {}", last_activity);
+                    }
+                    if lifecycle.stale {
+                        print!(", stale");
+                        if let Some(waiting_on) = &lifecycle.waiting_on {
+                            print!(" while waiting on {}", waiting_on);
+                        }
+                    }
+                    println!("]");
+                }
+            }
+            ProjectCommand::Show { key } => {
+                let project = Project::get_async(&key, database)
+                    .await?
+                    .ok_or_else(|| anyhow::anyhow!("project not found: {}", key))?;
+                println!("{}", serde_json::to_string_pretty(&project.contents)?);
+            }
+            ProjectCommand::Reload => {
+                let count = reload_catalog(database, Path::new(CATALOG_DIRECTORY)).await?;
+                cache::invalidate(database).await?;
+                println!("Loaded {} projects", count);
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,15 +1,20 @@
+use std::{collections::BTreeMap, time::SystemTime};
+
 use async_trait::async_trait;
 use bonsaidb::core::{
     document::{CollectionDocument, Emit},
     schema::{
-        Collection, CollectionName, CollectionViewSchema, DefaultViewSerialization, Name,
-        Qualified, Schema, SchemaName, Schematic, SerializedCollection, View, ViewMapResult,
+        view::map::Mappings, Collection, CollectionName, CollectionViewSchema,
+        DefaultViewSerialization, Name, Qualified, ReduceResult, Schema, SchemaName, Schematic,
+        SerializedCollection, View, ViewMapResult, ViewMappedValue,
     },
 };
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use transmog_json::{serde_json::Value, Json};
 
+use crate::{manifests::RepositoryManifest, projects::Project, summaries::DailySummary};
+
 #[derive(Debug)]
 pub struct Projects;
 
@@ -20,6 +25,10 @@ impl Schema for Projects {
 
     fn define_collections(schema: &mut Schematic) -> Result<(), bonsaidb::core::Error> {
         schema.define_collection::<Event>()?;
+        schema.define_collection::<SearchEntry>()?;
+        schema.define_collection::<Project>()?;
+        schema.define_collection::<RepositoryManifest>()?;
+        schema.define_collection::<DailySummary>()?;
         Ok(())
     }
 }
@@ -62,7 +71,13 @@ impl Collection for Event {
 
     fn define_views(schema: &mut Schematic) -> Result<(), bonsaidb::core::Error> {
         schema.define_view(GitHubEventById)?;
-        schema.define_view(GitHubEventByDate)?;
+        schema.define_view(GitHubEventByTimestamp)?;
+        schema.define_view(GitHubEventByRepository)?;
+        schema.define_view(GitHubLatestActivity)?;
+        schema.define_view(GitHubEventByActor)?;
+        schema.define_view(GitHubActivityCounts)?;
+        schema.define_view(GitHubDailyActivityCounts)?;
+        schema.define_view(GitHubContributorCounts)?;
         Ok(())
     }
 }
@@ -98,29 +113,256 @@ impl CollectionViewSchema for GitHubEventById {
 
 impl DefaultViewSerialization for GitHubEventById {}
 
+/// Events keyed by the time they were created.
+#[derive(Debug, Clone)]
+pub struct GitHubEventByTimestamp;
+
+impl View for GitHubEventByTimestamp {
+    type Collection = Event;
+    type Key = SystemTime;
+    type Value = ();
+
+    fn name(&self) -> Name {
+        Name::new("by-timestamp")
+    }
+}
+
+impl CollectionViewSchema for GitHubEventByTimestamp {
+    type View = Self;
+    fn map(&self, document: CollectionDocument<Event>) -> ViewMapResult<Self> {
+        document
+            .header
+            .emit_key(SystemTime::from(document.contents.created_at))
+    }
+}
+
+impl DefaultViewSerialization for GitHubEventByTimestamp {}
+
+/// Events keyed by the full name of the repository (`owner/name`) and the time
+/// they were created. Query with a key range to load a single repository's
+/// events.
 #[derive(Debug, Clone)]
-pub struct GitHubEventByDate;
+pub struct GitHubEventByRepository;
 
-impl View for GitHubEventByDate {
+impl View for GitHubEventByRepository {
+    type Collection = Event;
+    type Key = (String, SystemTime);
+    type Value = ();
+
+    fn name(&self) -> Name {
+        Name::new("by-repository")
+    }
+}
+
+impl CollectionViewSchema for GitHubEventByRepository {
+    type View = Self;
+    fn map(&self, document: CollectionDocument<Event>) -> ViewMapResult<Self> {
+        document.header.emit_key((
+            document.contents.repository.name,
+            SystemTime::from(document.contents.created_at),
+        ))
+    }
+}
+
+impl DefaultViewSerialization for GitHubEventByRepository {}
+
+/// The time of the most recent event keyed by the full name of the repository
+/// (`owner/name`). Use `reduce_grouped()` to get every repository's latest
+/// activity at once.
+#[derive(Debug, Clone)]
+pub struct GitHubLatestActivity;
+
+impl View for GitHubLatestActivity {
     type Collection = Event;
     type Key = String;
+    type Value = SystemTime;
+
+    fn name(&self) -> Name {
+        Name::new("latest-activity")
+    }
+}
+
+impl CollectionViewSchema for GitHubLatestActivity {
+    type View = Self;
+    fn map(&self, document: CollectionDocument<Event>) -> ViewMapResult<Self> {
+        document.header.emit_key_and_value(
+            document.contents.repository.name,
+            SystemTime::from(document.contents.created_at),
+        )
+    }
+
+    fn reduce(
+        &self,
+        mappings: &[ViewMappedValue<Self::View>],
+        _rereduce: bool,
+    ) -> ReduceResult<Self::View> {
+        Ok(mappings
+            .iter()
+            .map(|mapping| mapping.value)
+            .max()
+            .unwrap_or(SystemTime::UNIX_EPOCH))
+    }
+}
+
+impl DefaultViewSerialization for GitHubLatestActivity {}
+
+/// Events keyed by the login of the user who caused them and the time they were
+/// created.
+#[derive(Debug, Clone)]
+pub struct GitHubEventByActor;
+
+impl View for GitHubEventByActor {
+    type Collection = Event;
+    type Key = (String, SystemTime);
     type Value = ();
 
     fn name(&self) -> Name {
-        Name::new("by-date")
+        Name::new("by-actor")
+    }
+}
+
+impl CollectionViewSchema for GitHubEventByActor {
+    type View = Self;
+    fn map(&self, document: CollectionDocument<Event>) -> ViewMapResult<Self> {
+        document.header.emit_key((
+            document.contents.actor.login,
+            SystemTime::from(document.contents.created_at),
+        ))
+    }
+}
+
+impl DefaultViewSerialization for GitHubEventByActor {}
+
+/// The number of events keyed by the full name of the repository, the day
+/// (midnight UTC) the events were created, and the kind of event. Use
+/// `reduce_grouped()` to get the counts without loading any documents. Events
+/// from bot actors are not counted.
+#[derive(Debug, Clone)]
+pub struct GitHubActivityCounts;
+
+impl View for GitHubActivityCounts {
+    type Collection = Event;
+    type Key = (String, SystemTime, String);
+    type Value = u64;
+
+    fn name(&self) -> Name {
+        Name::new("activity-counts")
     }
 }
 
-impl CollectionViewSchema for GitHubEventByDate {
+impl CollectionViewSchema for GitHubActivityCounts {
     type View = Self;
     fn map(&self, document: CollectionDocument<Event>) -> ViewMapResult<Self> {
+        if document.contents.actor.login.ends_with("[bot]") {
+            return Ok(Mappings::none());
+        }
+
+        let day = document.contents.created_at.date().and_hms(0, 0, 0);
+        document.header.emit_key_and_value(
+            (
+                document.contents.repository.name,
+                SystemTime::from(day),
+                document.contents.kind,
+            ),
+            1,
+        )
+    }
+
+    fn reduce(
+        &self,
+        mappings: &[ViewMappedValue<Self::View>],
+        _rereduce: bool,
+    ) -> ReduceResult<Self::View> {
+        Ok(mappings.iter().map(|mapping| mapping.value).sum())
+    }
+}
+
+impl DefaultViewSerialization for GitHubActivityCounts {}
+
+/// The number of events keyed by the day (midnight UTC) the events were
+/// created, across all repositories. Use `reduce_grouped()` with a range of
+/// days to get the daily totals. Events from bot actors are not counted.
+#[derive(Debug, Clone)]
+pub struct GitHubDailyActivityCounts;
+
+impl View for GitHubDailyActivityCounts {
+    type Collection = Event;
+    type Key = SystemTime;
+    type Value = u64;
+
+    fn name(&self) -> Name {
+        Name::new("daily-activity-counts")
+    }
+}
+
+impl CollectionViewSchema for GitHubDailyActivityCounts {
+    type View = Self;
+    fn map(&self, document: CollectionDocument<Event>) -> ViewMapResult<Self> {
+        if document.contents.actor.login.ends_with("[bot]") {
+            return Ok(Mappings::none());
+        }
+
+        let day = document.contents.created_at.date().and_hms(0, 0, 0);
+        document.header.emit_key_and_value(SystemTime::from(day), 1)
+    }
+
+    fn reduce(
+        &self,
+        mappings: &[ViewMappedValue<Self::View>],
+        _rereduce: bool,
+    ) -> ReduceResult<Self::View> {
+        Ok(mappings.iter().map(|mapping| mapping.value).sum())
+    }
+}
+
+impl DefaultViewSerialization for GitHubDailyActivityCounts {}
+
+/// The number of events each user caused, keyed by the full name of the
+/// repository. Use `reduce()` with a repository's key to get the counts for
+/// that repository, keyed by login. Events from bot actors are not counted.
+#[derive(Debug, Clone)]
+pub struct GitHubContributorCounts;
+
+impl View for GitHubContributorCounts {
+    type Collection = Event;
+    type Key = String;
+    type Value = BTreeMap<String, u64>;
+
+    fn name(&self) -> Name {
+        Name::new("contributor-counts")
+    }
+}
+
+impl CollectionViewSchema for GitHubContributorCounts {
+    type View = Self;
+    fn map(&self, document: CollectionDocument<Event>) -> ViewMapResult<Self> {
+        if document.contents.actor.login.ends_with("[bot]") {
+            return Ok(Mappings::none());
+        }
+
+        let mut counts = BTreeMap::new();
+        counts.insert(document.contents.actor.login, 1);
         document
             .header
-            .emit_key(document.contents.created_at.format("%Y-%m-%d").to_string())
+            .emit_key_and_value(document.contents.repository.name, counts)
+    }
+
+    fn reduce(
+        &self,
+        mappings: &[ViewMappedValue<Self::View>],
+        _rereduce: bool,
+    ) -> ReduceResult<Self::View> {
+        let mut counts = BTreeMap::new();
+        for mapping in mappings {
+            for (login, count) in &mapping.value {
+                *counts.entry(login.clone()).or_default() += count;
+            }
+        }
+        Ok(counts)
     }
 }
 
-impl DefaultViewSerialization for GitHubEventByDate {}
+impl DefaultViewSerialization for GitHubContributorCounts {}
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct PushPayload {
@@ -143,6 +385,27 @@ pub struct Issue {
     pub id: u64,
     pub html_url: String,
     pub number: u64,
+    #[serde(default)]
+    pub body: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct PullRequestPayload {
+    pub action: String,
+    pub number: u64,
+    pub pull_request: PullRequest,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct PullRequest {
+    pub title: String,
+    pub id: u64,
+    pub html_url: String,
+    pub number: u64,
+    #[serde(default)]
+    pub merged: bool,
+    #[serde(default)]
+    pub body: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -160,6 +423,8 @@ pub struct Release {
     pub draft: bool,
     pub prerelease: bool,
     pub short_description_html: String,
+    #[serde(default)]
+    pub body: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -176,3 +441,106 @@ pub struct Author {
     pub name: String,
     pub email: String,
 }
+
+/// A piece of text extracted from an [`Event`] that can be found using
+/// full-text search. See the `search` module for how entries are created.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SearchEntry {
+    pub kind: SearchEntryKind,
+    pub repository: String,
+    pub title: String,
+    pub body: String,
+    pub url: String,
+    pub created_at: DateTime<Utc>,
+    /// The weight of each stemmed term found in `title` and `body`.
+    pub terms: BTreeMap<String, u32>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SearchEntryKind {
+    Commit,
+    Issue,
+    PullRequest,
+    Release,
+}
+
+#[async_trait]
+impl Collection for SearchEntry {
+    type PrimaryKey = u64;
+
+    fn collection_name() -> CollectionName {
+        CollectionName::new("khonsulabs", "search-entries")
+    }
+
+    fn define_views(schema: &mut Schematic) -> Result<(), bonsaidb::core::Error> {
+        schema.define_view(SearchEntryByTerm)?;
+        schema.define_view(SearchEntryByUrl)?;
+        Ok(())
+    }
+}
+
+impl SerializedCollection for SearchEntry {
+    type Contents = Self;
+    type Format = Json;
+
+    fn format() -> Self::Format {
+        Json::default()
+    }
+}
+
+/// The inverted index: each stemmed term mapped to the entries containing it,
+/// with the term's weight in that entry as the value.
+#[derive(Debug, Clone)]
+pub struct SearchEntryByTerm;
+
+impl View for SearchEntryByTerm {
+    type Collection = SearchEntry;
+    type Key = String;
+    type Value = u32;
+
+    fn name(&self) -> Name {
+        Name::new("by-term")
+    }
+}
+
+impl CollectionViewSchema for SearchEntryByTerm {
+    type View = Self;
+    fn map(&self, document: CollectionDocument<SearchEntry>) -> ViewMapResult<Self> {
+        document
+            .contents
+            .terms
+            .into_iter()
+            .map(|(term, weight)| document.header.emit_key_and_value(term, weight))
+            .collect()
+    }
+}
+
+impl DefaultViewSerialization for SearchEntryByTerm {}
+
+#[derive(Debug, Clone)]
+pub struct SearchEntryByUrl;
+
+impl View for SearchEntryByUrl {
+    type Collection = SearchEntry;
+    type Key = String;
+    type Value = ();
+
+    fn name(&self) -> Name {
+        Name::new("by-url")
+    }
+}
+
+impl CollectionViewSchema for SearchEntryByUrl {
+    type View = Self;
+
+    fn unique(&self) -> bool {
+        true
+    }
+
+    fn map(&self, document: CollectionDocument<SearchEntry>) -> ViewMapResult<Self> {
+        document.header.emit_key(document.contents.url)
+    }
+}
+
+impl DefaultViewSerialization for SearchEntryByUrl {}
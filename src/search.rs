@@ -0,0 +1,404 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    ops::Range,
+};
+
+use bonsaidb::core::{
+    connection::AsyncConnection,
+    schema::SerializedCollection,
+    transaction::{Operation, Transaction},
+};
+use once_cell::sync::Lazy;
+use rust_stemmers::{Algorithm, Stemmer};
+use serde::Serialize;
+use tera::escape_html;
+use transmog_json::serde_json;
+
+use crate::schema::{
+    Event, IssuesPayload, PullRequestPayload, PushPayload, ReleasePayload, SearchEntry,
+    SearchEntryByTerm, SearchEntryByUrl, SearchEntryKind,
+};
+
+/// The weight of a term found in an entry's title. Terms found in the body
+/// have a weight of 1.
+const TITLE_WEIGHT: u32 = 3;
+/// The number of words shown around the first match in a result's snippet.
+const SNIPPET_WORDS: usize = 30;
+
+const STOP_WORDS: [&str; 32] = [
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "if", "in", "into", "is", "it",
+    "no", "not", "of", "on", "or", "such", "that", "the", "their", "then", "there", "these",
+    "they", "this", "to", "was", "with",
+];
+
+static STEMMER: Lazy<Stemmer> = Lazy::new(|| Stemmer::create(Algorithm::English));
+
+/// Splits `text` into words, returning the byte range of each word alongside
+/// its stemmed term. Stop words are skipped.
+fn tokenize(text: &str) -> impl Iterator<Item = (Range<usize>, String)> + '_ {
+    let mut chars = text.char_indices().peekable();
+    std::iter::from_fn(move || loop {
+        let (start, _) = chars.find(|(_, ch)| ch.is_alphanumeric())?;
+        let mut end = text.len();
+        while let Some(&(index, ch)) = chars.peek() {
+            if ch.is_alphanumeric() {
+                chars.next();
+            } else {
+                end = index;
+                break;
+            }
+        }
+
+        let word = text[start..end].to_lowercase();
+        if word.len() < 2 || STOP_WORDS.contains(&word.as_str()) {
+            continue;
+        }
+        return Some((start..end, STEMMER.stem(&word).into_owned()));
+    })
+}
+
+fn weigh_terms(title: &str, body: &str) -> BTreeMap<String, u32> {
+    let mut terms = BTreeMap::new();
+    for (_, term) in tokenize(title) {
+        *terms.entry(term).or_default() += TITLE_WEIGHT;
+    }
+    for (_, term) in tokenize(body) {
+        *terms.entry(term).or_default() += 1;
+    }
+    terms
+}
+
+fn entry(
+    kind: SearchEntryKind,
+    event: &Event,
+    title: String,
+    body: String,
+    url: String,
+) -> SearchEntry {
+    SearchEntry {
+        kind,
+        repository: event.repository.name.clone(),
+        terms: weigh_terms(&title, &body),
+        title,
+        body,
+        url,
+        created_at: event.created_at,
+    }
+}
+
+/// Extracts the searchable text from `event`. A push produces one entry per
+/// commit.
+pub fn entries_for_event(event: &Event) -> anyhow::Result<Vec<SearchEntry>> {
+    let mut entries = Vec::new();
+    match event.kind.as_str() {
+        "PushEvent" => {
+            let push = serde_json::value::from_value::<PushPayload>(event.payload.clone())?;
+            for commit in push.commits.into_iter().filter(|commit| commit.distinct) {
+                let (title, body) = commit
+                    .message
+                    .split_once('\n')
+                    .unwrap_or((commit.message.as_str(), ""));
+                entries.push(entry(
+                    SearchEntryKind::Commit,
+                    event,
+                    title.trim().to_string(),
+                    body.trim().to_string(),
+                    format!(
+                        "https://github.com/{}/commit/{}",
+                        event.repository.name, commit.sha
+                    ),
+                ));
+            }
+        }
+        "IssuesEvent" => {
+            let payload = serde_json::value::from_value::<IssuesPayload>(event.payload.clone())?;
+            entries.push(entry(
+                SearchEntryKind::Issue,
+                event,
+                payload.issue.title,
+                payload.issue.body.unwrap_or_default(),
+                payload.issue.html_url,
+            ));
+        }
+        "PullRequestEvent" => {
+            let payload =
+                serde_json::value::from_value::<PullRequestPayload>(event.payload.clone())?;
+            entries.push(entry(
+                SearchEntryKind::PullRequest,
+                event,
+                payload.pull_request.title,
+                payload.pull_request.body.unwrap_or_default(),
+                payload.pull_request.html_url,
+            ));
+        }
+        "ReleaseEvent" => {
+            let payload = serde_json::value::from_value::<ReleasePayload>(event.payload.clone())?;
+            if !payload.release.draft {
+                entries.push(entry(
+                    SearchEntryKind::Release,
+                    event,
+                    payload.release.name,
+                    payload.release.body.unwrap_or_default(),
+                    payload.release.html_url,
+                ));
+            }
+        }
+        _ => {}
+    }
+    Ok(entries)
+}
+
+/// Adds the operations that index the searchable text from `event` to
+/// `transaction`. Issues and pull requests are indexed once per URL, and later
+/// events replace the existing entry so that edited titles are picked up.
+pub async fn index_event<C: AsyncConnection>(
+    database: &C,
+    event: &Event,
+    transaction: &mut Transaction,
+) -> anyhow::Result<()> {
+    let mut urls = HashSet::new();
+    for entry in entries_for_event(event)? {
+        if !urls.insert(entry.url.clone()) {
+            continue;
+        }
+        let existing = database
+            .view::<SearchEntryByUrl>()
+            .with_key(entry.url.clone())
+            .query_with_collection_docs()
+            .await?;
+        if let Some(mapping) = existing.into_iter().next() {
+            if mapping.document.contents.created_at <= entry.created_at {
+                transaction.push(Operation::update_serialized::<SearchEntry>(
+                    mapping.document.header,
+                    &entry,
+                )?);
+            }
+        } else {
+            transaction.push(Operation::push_serialized::<SearchEntry>(&entry)?);
+        }
+    }
+    Ok(())
+}
+
+/// Indexes all stored events if the index is empty, which happens the first
+/// time the search index is used with an existing database.
+pub async fn index_existing_events<C: AsyncConnection>(database: &C) -> anyhow::Result<()> {
+    if database.collection::<SearchEntry>().all().count().await? > 0 {
+        return Ok(());
+    }
+
+    let events = Event::all_async(database).await?;
+    tracing::info!("Building search index from {} events", events.len());
+    for event in &events {
+        let mut transaction = Transaction::new();
+        index_event(database, &event.contents, &mut transaction).await?;
+        database.apply_transaction(transaction).await?;
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Debug)]
+pub struct SearchResult {
+    pub kind: SearchEntryKind,
+    pub repository: String,
+    pub url: String,
+    pub created_at: String,
+    pub score: f64,
+    /// The escaped title with matches wrapped in `<mark>`.
+    pub title_html: String,
+    /// An escaped excerpt of the body with matches wrapped in `<mark>`.
+    pub snippet_html: String,
+}
+
+/// Returns up to `limit` entries matching any of the terms in `query`,
+/// ranked by the weight of each matching term scaled by how rare the term is.
+pub async fn search<C: AsyncConnection>(
+    database: &C,
+    query: &str,
+    limit: usize,
+) -> anyhow::Result<Vec<SearchResult>> {
+    let terms = tokenize(query)
+        .map(|(_, term)| term)
+        .collect::<HashSet<_>>();
+    if terms.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let total_entries = database.collection::<SearchEntry>().all().count().await? as f64;
+    let mappings = database
+        .view::<SearchEntryByTerm>()
+        .with_keys(terms.iter().cloned())
+        .query()
+        .await?;
+    let mut document_frequency = HashMap::<&str, f64>::new();
+    for mapping in &mappings {
+        *document_frequency.entry(&mapping.key).or_default() += 1.;
+    }
+
+    let mut scores = HashMap::<u64, f64>::new();
+    for mapping in &mappings {
+        let inverse_frequency = (total_entries / document_frequency[mapping.key.as_str()]).ln_1p();
+        *scores.entry(mapping.source.id.deserialize()?).or_default() +=
+            f64::from(mapping.value) * inverse_frequency;
+    }
+    let mut ranked = scores.into_iter().collect::<Vec<_>>();
+    ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(b.0.cmp(&a.0)));
+    ranked.truncate(limit);
+
+    let documents = SearchEntry::get_multiple_async(ranked.iter().map(|(id, _)| *id), database)
+        .await?
+        .into_iter()
+        .map(|document| (document.header.id, document.contents))
+        .collect::<HashMap<_, _>>();
+    Ok(ranked
+        .into_iter()
+        .filter_map(|(id, score)| {
+            let entry = documents.get(&id)?;
+            Some(SearchResult {
+                kind: entry.kind,
+                repository: entry.repository.clone(),
+                url: entry.url.clone(),
+                created_at: entry.created_at.format("%B %e, %Y").to_string(),
+                score,
+                title_html: highlight(&entry.title, &terms, None),
+                snippet_html: highlight(&entry.body, &terms, Some(SNIPPET_WORDS)),
+            })
+        })
+        .collect())
+}
+
+/// Escapes `text` for HTML, wrapping each word whose stem is in `terms` in
+/// `<mark>`. If `max_words` is provided, only an excerpt starting a few words
+/// before the first match is returned.
+fn highlight(text: &str, terms: &HashSet<String>, max_words: Option<usize>) -> String {
+    let words = tokenize(text).collect::<Vec<_>>();
+    let (start, end) = match max_words {
+        Some(max_words) if words.len() > max_words => {
+            let first_match = words
+                .iter()
+                .position(|(_, term)| terms.contains(term))
+                .unwrap_or_default();
+            let first_word = first_match.saturating_sub(5).min(words.len() - max_words);
+            let last_word = first_word + max_words - 1;
+            (
+                if first_word == 0 {
+                    0
+                } else {
+                    words[first_word].0.start
+                },
+                words[last_word].0.end,
+            )
+        }
+        _ => (0, text.len()),
+    };
+
+    let mut html = String::new();
+    if start > 0 {
+        html.push_str("&hellip;");
+    }
+    let mut position = start;
+    for (range, term) in &words {
+        if range.start < start || range.end > end || !terms.contains(term) {
+            continue;
+        }
+        html.push_str(&escape_html(&text[position..range.start]));
+        html.push_str("<mark>");
+        html.push_str(&escape_html(&text[range.clone()]));
+        html.push_str("</mark>");
+        position = range.end;
+    }
+    html.push_str(&escape_html(&text[position..end]));
+    if end < text.len() {
+        html.push_str("&hellip;");
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use bonsaidb::core::schema::SerializedCollection;
+
+    use super::*;
+    use crate::{
+        backup::SnapshotLock,
+        config::ActivitySection,
+        database::{self, VaultKeyLocation},
+        testing::{event, issue, TestDirectory},
+        updater,
+    };
+
+    fn terms(text: &str) -> Vec<String> {
+        tokenize(text).map(|(_, term)| term).collect()
+    }
+
+    #[test]
+    fn stop_words_are_dropped_and_words_stemmed() {
+        assert_eq!(terms("The fix is in the release"), ["fix", "releas"]);
+        assert_eq!(terms("compacting"), terms("compaction"));
+        assert_eq!(
+            tokenize("a B-tree").collect::<Vec<_>>(),
+            [(4..8, String::from("tree"))]
+        );
+    }
+
+    #[test]
+    fn title_terms_outrank_body_terms() {
+        let terms = weigh_terms("Compact views", "Views are compacted when compacting");
+        assert_eq!(terms["compact"], TITLE_WEIGHT + 2);
+        assert_eq!(terms["view"], TITLE_WEIGHT + 1);
+        assert!(terms["compact"] > terms["when"]);
+        assert!(!terms.contains_key("are"));
+    }
+
+    #[test]
+    fn matches_are_highlighted_and_escaped() {
+        let terms = HashSet::from([String::from("compact")]);
+        assert_eq!(
+            highlight("Fix <compaction> & views", &terms, None),
+            "Fix &lt;<mark>compaction</mark>&gt; &amp; views"
+        );
+
+        let terms = HashSet::from([String::from("seven")]);
+        assert_eq!(
+            highlight(
+                "one two three four five six seven eight nine ten",
+                &terms,
+                Some(8)
+            ),
+            "&hellip;two three four five six <mark>seven</mark> eight nine&hellip;"
+        );
+        assert_eq!(
+            highlight("one two seven", &terms, Some(8)),
+            "one two <mark>seven</mark>"
+        );
+    }
+
+    #[tokio::test]
+    async fn reindexed_urls_replace_their_entry() -> anyhow::Result<()> {
+        let directory = TestDirectory::new("search-test");
+        let database = database::open_local(
+            &directory.join("projects.bonsaidb"),
+            &VaultKeyLocation::Storage,
+        )
+        .await?;
+
+        let activity = ActivitySection::default();
+        for (id, created_at, title) in [
+            (1, "2022-03-01T10:00:00Z", "Views aren't compacted"),
+            (2, "2022-03-02T10:00:00Z", "Compaction skips views"),
+        ] {
+            let event = event(id, "IssuesEvent", created_at, issue("opened", 7, title));
+            updater::insert_event(&database, event, &activity, &SnapshotLock::default()).await?;
+        }
+
+        let entries = SearchEntry::all_async(&database).await?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].contents.title, "Compaction skips views");
+
+        let results = search(&database, "skipping", 10).await?;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].title_html, "Compaction <mark>skips</mark> views");
+        assert!(search(&database, "aren't", 10).await?.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,96 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+};
+
+/// Tells the webserver and background jobs to stop once the process
+/// receives SIGINT or SIGTERM, or once [`Shutdown::request`] is called.
+/// Clones all observe the same request.
+#[derive(Clone, Debug)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<bool>>,
+    requested: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    /// Returns a [`Shutdown`] that is requested when the process receives
+    /// SIGINT or SIGTERM. Once this is called, the signals no longer end the
+    /// process on their own.
+    pub fn on_signals() -> anyhow::Result<Self> {
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        let mut terminate = signal(SignalKind::terminate())?;
+        let shutdown = Self::never();
+        let signaled = shutdown.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = interrupt.recv() => {}
+                _ = terminate.recv() => {}
+            }
+            tracing::info!("Shutting down");
+            signaled.request();
+        });
+        Ok(shutdown)
+    }
+
+    /// Returns a [`Shutdown`] that is only requested by calling
+    /// [`Shutdown::request`], for commands that leave the signals' default
+    /// behavior in place.
+    pub fn never() -> Self {
+        let (sender, requested) = watch::channel(false);
+        Self {
+            sender: Arc::new(sender),
+            requested,
+        }
+    }
+
+    /// Requests shutdown, such as when one of the background jobs fails.
+    pub fn request(&self) {
+        // Sending only fails without receivers, and `self` is one.
+        let _ = self.sender.send(true);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.requested.borrow()
+    }
+
+    /// Waits until shutdown is requested.
+    pub async fn requested(mut self) {
+        while !*self.requested.borrow_and_update() {
+            if self.requested.changed().await.is_err() {
+                // Without a sender, shutdown can never be requested.
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+
+    /// Sleeps for `duration`, returning early if shutdown is requested.
+    /// Returns true if shutdown was requested.
+    pub async fn sleep(&self, duration: Duration) -> bool {
+        tokio::select! {
+            _ = self.clone().requested() => true,
+            _ = tokio::time::sleep(duration) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn requests_wake_sleepers() {
+        let shutdown = Shutdown::never();
+        assert!(!shutdown.sleep(Duration::from_millis(10)).await);
+
+        let sleeping = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move { shutdown.sleep(Duration::from_secs(60)).await }
+        });
+        shutdown.request();
+        assert!(sleeping.await.unwrap());
+        assert!(shutdown.is_requested());
+        shutdown.requested().await;
+    }
+}
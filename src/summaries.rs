@@ -0,0 +1,363 @@
+use std::collections::{BTreeMap, HashMap};
+
+use async_trait::async_trait;
+use bonsaidb::core::{
+    connection::{AsyncConnection, Range},
+    pubsub::AsyncPubSub,
+    schema::{Collection, CollectionName, Qualified, Schematic, SerializedCollection},
+    transaction::{Operation, Transaction},
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use structopt::StructOpt;
+use transmog_json::{serde_json, Json};
+
+use crate::{
+    api, cache,
+    config::ActivitySection,
+    html,
+    schema::{Event, GitHubEventByTimestamp, IssuesPayload, PushPayload, Release, ReleasePayload},
+};
+
+/// The activity for a single UTC day, keyed by the repository's name without
+/// its owner. Stored using `iso_date` as the primary key, and kept up to date
+/// by the updater as events are received.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct DailySummary {
+    pub display: String,
+    pub iso_date: String,
+    pub repositories: HashMap<String, ActiveRepository>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ActiveRepository {
+    pub url: String,
+    pub forked_from: Option<String>,
+    pub commit_authors: HashMap<String, HashMap<String, usize>>,
+    pub issues_closed: Vec<ClosedIssue>,
+    pub releases: Vec<Release>,
+}
+
+impl ActiveRepository {
+    fn is_empty(&self) -> bool {
+        self.commit_authors.is_empty() && self.issues_closed.is_empty() && self.releases.is_empty()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ClosedIssue {
+    pub id: u64,
+    pub author: String,
+    pub url: String,
+    pub title: String,
+}
+
+#[async_trait]
+impl Collection for DailySummary {
+    type PrimaryKey = String;
+
+    fn collection_name() -> CollectionName {
+        CollectionName::new("khonsulabs", "daily-summaries")
+    }
+
+    fn define_views(_schema: &mut Schematic) -> Result<(), bonsaidb::core::Error> {
+        Ok(())
+    }
+}
+
+impl SerializedCollection for DailySummary {
+    type Contents = Self;
+    type Format = Json;
+
+    fn format() -> Self::Format {
+        Json::default()
+    }
+}
+
+impl DailySummary {
+    pub fn new(date: NaiveDate) -> Self {
+        Self {
+            display: date.format("%A, %B %e, %Y").to_string(),
+            iso_date: date.format("%Y-%m-%d").to_string(),
+            repositories: HashMap::new(),
+        }
+    }
+
+    /// Adds `event` to this summary. Events from bots are ignored.
+    pub fn record(&mut self, event: &Event, activity: &ActivitySection) -> anyhow::Result<()> {
+        if event.actor.login.ends_with("[bot]") {
+            return Ok(());
+        }
+
+        let local_repository_name = event.repository.name.split('/').nth(1).unwrap();
+        let forked_repo = activity.forked_repository(local_repository_name);
+        let repository = self
+            .repositories
+            .entry(local_repository_name.to_string())
+            .or_insert_with(|| ActiveRepository {
+                url: format!(
+                    "https://github.com/{}",
+                    forked_repo.unwrap_or(&event.repository.name)
+                ),
+                forked_from: forked_repo.map(|r| r.to_string()),
+                ..ActiveRepository::default()
+            });
+
+        match event.kind.as_str() {
+            "IssuesEvent" => {
+                let payload =
+                    serde_json::value::from_value::<IssuesPayload>(event.payload.clone())?;
+                if payload.action == "closed" {
+                    repository.issues_closed.push(ClosedIssue {
+                        id: payload.issue.number,
+                        author: event.actor.login.clone(),
+                        url: payload.issue.html_url.to_string(),
+                        title: payload.issue.title.clone(),
+                    });
+                }
+            }
+            "PushEvent" => {
+                let push = serde_json::value::from_value::<PushPayload>(event.payload.clone())?;
+                for commit in &push.commits {
+                    if forked_repo.is_none()
+                        || activity.contributor_emails.contains(&commit.author.email)
+                    {
+                        let repository = repository
+                            .commit_authors
+                            .entry(event.actor.login.clone())
+                            .or_default();
+                        repository
+                            .entry(push.reference.split('/').next_back().unwrap().to_string())
+                            .and_modify(|count| *count += 1)
+                            .or_insert(1);
+                    }
+                }
+            }
+            "ReleaseEvent" => {
+                let mut payload =
+                    serde_json::value::from_value::<ReleasePayload>(event.payload.clone())?;
+                if !payload.release.draft {
+                    payload.release.short_description_html =
+                        html::sanitize(&payload.release.short_description_html);
+                    repository.releases.push(payload.release);
+                }
+            }
+            _ => {}
+        }
+
+        if repository.is_empty() {
+            self.repositories.remove(local_repository_name);
+        }
+        Ok(())
+    }
+}
+
+/// Adds the operation that records `event` in the summary of the day it was
+/// created on to `transaction`. An existing summary is updated at the revision
+/// read here, so the transaction fails rather than overwriting a change made
+/// in the meantime.
+pub async fn record_event<C: AsyncConnection>(
+    database: &C,
+    event: &Event,
+    activity: &ActivitySection,
+    transaction: &mut Transaction,
+) -> anyhow::Result<()> {
+    let date = event.created_at.date().naive_utc();
+    let key = date.format("%Y-%m-%d").to_string();
+    match DailySummary::get_async(&key, database).await? {
+        Some(mut document) => {
+            document.contents.record(event, activity)?;
+            transaction.push(Operation::update_serialized::<DailySummary>(
+                document.header,
+                &document.contents,
+            )?);
+        }
+        None => {
+            let mut summary = DailySummary::new(date);
+            summary.record(event, activity)?;
+            if !summary.repositories.is_empty() {
+                transaction.push(Operation::insert_serialized::<DailySummary>(
+                    Some(key),
+                    &summary,
+                )?);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the summaries of the days between `from` and `to` (inclusive)
+/// that had activity, most recent first.
+pub async fn summaries<C: AsyncConnection>(
+    database: &C,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> anyhow::Result<Vec<DailySummary>> {
+    let mut summaries = DailySummary::list_async(date_keys(from, to), database)
+        .await?
+        .into_iter()
+        .map(|document| document.contents)
+        .collect::<Vec<_>>();
+    summaries.reverse();
+    Ok(summaries)
+}
+
+/// Returns the range of summary ids from `from` through `to` (inclusive).
+fn date_keys(from: NaiveDate, to: NaiveDate) -> Range<String> {
+    let start = from.format("%Y-%m-%d").to_string();
+    match to.succ_opt() {
+        Some(end) => (start..end.format("%Y-%m-%d").to_string()).into(),
+        None => (start..).into(),
+    }
+}
+
+/// Returns the date of the oldest day with activity.
+pub async fn oldest_day<C: AsyncConnection>(database: &C) -> anyhow::Result<Option<NaiveDate>> {
+    let all: Range<String> = (..).into();
+    let oldest = DailySummary::list_async(all, database).limit(1).await?;
+    Ok(match oldest.into_iter().next() {
+        Some(document) => Some(NaiveDate::parse_from_str(&document.header.id, "%Y-%m-%d")?),
+        None => None,
+    })
+}
+
+/// Recomputes the summaries of the days between `from` and `to` (inclusive)
+/// from the stored events, replacing them in a single transaction. Returns
+/// the number of days with activity.
+pub async fn rebuild_summaries<C: AsyncConnection>(
+    database: &C,
+    from: NaiveDate,
+    to: NaiveDate,
+    activity: &ActivitySection,
+) -> anyhow::Result<usize> {
+    let start = api::start_of_day(from);
+    let end = api::end_of_day(to);
+    let events = database
+        .view::<GitHubEventByTimestamp>()
+        .with_key_range(start..end)
+        .query_with_collection_docs()
+        .await?;
+
+    let mut rebuilt = BTreeMap::<String, DailySummary>::new();
+    for mapping in &events {
+        let event = &mapping.document.contents;
+        let date = event.created_at.date().naive_utc();
+        rebuilt
+            .entry(date.format("%Y-%m-%d").to_string())
+            .or_insert_with(|| DailySummary::new(date))
+            .record(event, activity)?;
+    }
+    rebuilt.retain(|_, summary| !summary.repositories.is_empty());
+
+    let mut transaction = Transaction::new();
+    for existing in DailySummary::list_async(date_keys(from, to), database).await? {
+        if !rebuilt.contains_key(&existing.header.id) {
+            transaction.push(Operation::delete(
+                DailySummary::collection_name(),
+                existing.header.try_into()?,
+            ));
+        }
+    }
+    for (key, summary) in &rebuilt {
+        transaction.push(Operation::overwrite_serialized::<DailySummary>(
+            key.clone(),
+            summary,
+        )?);
+    }
+    if !transaction.operations.is_empty() {
+        database.apply_transaction(transaction).await?;
+    }
+    Ok(rebuilt.len())
+}
+
+/// Returns the dates of the oldest and newest stored events.
+async fn event_date_range<C: AsyncConnection>(
+    database: &C,
+) -> anyhow::Result<Option<(NaiveDate, NaiveDate)>> {
+    let oldest = database
+        .view::<GitHubEventByTimestamp>()
+        .ascending()
+        .limit(1)
+        .query()
+        .await?;
+    let newest = database
+        .view::<GitHubEventByTimestamp>()
+        .descending()
+        .limit(1)
+        .query()
+        .await?;
+    Ok(oldest
+        .into_iter()
+        .next()
+        .zip(newest.into_iter().next())
+        .map(|(oldest, newest)| {
+            (
+                DateTime::<Utc>::from(oldest.key).date().naive_utc(),
+                DateTime::<Utc>::from(newest.key).date().naive_utc(),
+            )
+        }))
+}
+
+/// Builds the summaries of every stored event if there are none, which
+/// happens the first time summaries are used with an existing database.
+pub async fn summarize_existing_events<C: AsyncConnection>(
+    database: &C,
+    activity: &ActivitySection,
+) -> anyhow::Result<()> {
+    if database.collection::<DailySummary>().all().count().await? > 0 {
+        return Ok(());
+    }
+
+    if let Some((from, to)) = event_date_range(database).await? {
+        let days = rebuild_summaries(database, from, to, activity).await?;
+        tracing::info!("Summarized {} days of existing events", days);
+    }
+    Ok(())
+}
+
+/// Commands for the materialized daily summaries.
+#[derive(StructOpt, Debug)]
+pub enum SummaryCommand {
+    /// Recomputes the daily summaries from the stored events.
+    Rebuild {
+        /// The first day to rebuild (YYYY-MM-DD). Defaults to the day of the
+        /// oldest event.
+        #[structopt(long)]
+        from: Option<NaiveDate>,
+        /// The last day to rebuild (YYYY-MM-DD). Defaults to today.
+        #[structopt(long)]
+        to: Option<NaiveDate>,
+    },
+}
+
+impl SummaryCommand {
+    pub async fn execute<C: AsyncConnection + AsyncPubSub>(
+        self,
+        database: &C,
+        activity: &ActivitySection,
+    ) -> anyhow::Result<()> {
+        match self {
+            SummaryCommand::Rebuild { from, to } => {
+                let today = Utc::now().date().naive_utc();
+                let from = match from {
+                    Some(from) => from,
+                    None => match event_date_range(database).await? {
+                        Some((oldest, _)) => oldest,
+                        None => today,
+                    },
+                };
+                let to = to.unwrap_or(today);
+                if from > to {
+                    anyhow::bail!("--from must not be after --to");
+                }
+                let days = rebuild_summaries(database, from, to, activity).await?;
+                cache::invalidate(database).await?;
+                println!(
+                    "Rebuilt {} days with activity between {} and {}",
+                    days, from, to
+                );
+                Ok(())
+            }
+        }
+    }
+}
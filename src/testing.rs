@@ -0,0 +1,82 @@
+//! Fixtures shared by the tests of several modules.
+
+use std::{
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+use transmog_json::serde_json::{self, json, Value};
+
+use crate::schema::Event;
+
+/// An empty directory within the system's temporary directory, which is
+/// removed along with its contents when dropped.
+#[derive(Debug)]
+pub struct TestDirectory {
+    path: PathBuf,
+}
+
+impl TestDirectory {
+    /// Creates the directory, named after `name` and this process so that
+    /// concurrent test runs don't share it.
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "khonsulabs-projects-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self { path }
+    }
+}
+
+impl Deref for TestDirectory {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TestDirectory {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Returns an event by `ecton` in `khonsulabs/bonsaidb`.
+pub fn event(id: u64, kind: &str, created_at: &str, payload: Value) -> Event {
+    serde_json::from_value(json!({
+        "id": id.to_string(),
+        "type": kind,
+        "actor": {
+            "id": 1,
+            "login": "ecton",
+            "url": "https://api.github.com/users/ecton",
+            "avatar_url": "https://avatars.githubusercontent.com/u/1",
+        },
+        "repo": {
+            "id": 2,
+            "name": "khonsulabs/bonsaidb",
+            "url": "https://api.github.com/repos/khonsulabs/bonsaidb",
+        },
+        "payload": payload,
+        "public": true,
+        "created_at": created_at,
+    }))
+    .unwrap()
+}
+
+/// Returns the payload of an `IssuesEvent`.
+pub fn issue(action: &str, number: u64, title: &str) -> Value {
+    json!({
+        "action": action,
+        "issue": {
+            "id": number,
+            "number": number,
+            "title": title,
+            "html_url": format!("https://github.com/khonsulabs/bonsaidb/issues/{}", number),
+        },
+    })
+}
@@ -1,43 +1,146 @@
-use std::time::Duration;
+use std::{
+    collections::HashSet,
+    io::{BufRead, Write},
+    sync::Arc,
+    time::Duration,
+};
 
-use bonsaidb::{
-    core::{connection::AsyncConnection, schema::SerializedCollection},
-    local::AsyncDatabase,
+use bonsaidb::core::{
+    connection::AsyncConnection,
+    pubsub::AsyncPubSub,
+    transaction::{Operation, OperationResult, Transaction},
 };
 use reqwest::{
     header::{ACCEPT, USER_AGENT},
-    Client,
+    Client, RequestBuilder,
 };
 use transmog_json::serde_json;
 
-use crate::schema::{Event, GitHubEventById};
+use crate::{
+    backup::SnapshotLock,
+    cache,
+    config::{ActivitySection, Config, GitHubSection},
+    live,
+    schema::{Event, GitHubEventById, GitHubEventByTimestamp},
+    search,
+    shutdown::Shutdown,
+    summaries,
+};
+
+/// Fetches new events every poll interval until shutdown is requested.
+/// Snapshots taken using `snapshots` wait for each event to be stored, and
+/// for the search index and summaries of existing events to be built.
+pub async fn update_events_periodically<C: AsyncConnection + AsyncPubSub>(
+    storage: C,
+    config: Arc<Config>,
+    snapshots: SnapshotLock,
+    shutdown: Shutdown,
+) -> anyhow::Result<()> {
+    let writing = snapshots.writing().await;
+    search::index_existing_events(&storage).await?;
+    summaries::summarize_existing_events(&storage, &config.activity).await?;
+    drop(writing);
 
-pub async fn update_events_periodically(storage: AsyncDatabase) -> anyhow::Result<()> {
     let instance = Client::new();
-    loop {
+    while !shutdown.is_requested() {
         tracing::info!("Fetching new events from GitHub");
-        fetch_new_events(&storage, &instance).await?;
+        if fetch_events(&storage, &instance, &config, false, &snapshots, &shutdown).await? > 0 {
+            cache::invalidate(&storage).await?;
+        }
         tracing::info!("Sleeping");
-        tokio::time::sleep(Duration::from_secs(300)).await;
+        if shutdown
+            .sleep(Duration::from_secs(config.github.poll_interval_secs))
+            .await
+        {
+            break;
+        }
+    }
+    tracing::info!("Stopped fetching events");
+    Ok(())
+}
+
+/// Fetches events from GitHub once. If `backfill` is true, every page GitHub
+/// returns is requested and any missing events are inserted, rather than
+/// stopping at the first event that is already stored. Returns the number of
+/// events inserted.
+pub async fn update_once<C: AsyncConnection + AsyncPubSub>(
+    database: &C,
+    config: &Config,
+    backfill: bool,
+    shutdown: &Shutdown,
+) -> anyhow::Result<usize> {
+    search::index_existing_events(database).await?;
+    summaries::summarize_existing_events(database, &config.activity).await?;
+    // No snapshots are taken while this command runs.
+    let snapshots = SnapshotLock::default();
+    let inserted = fetch_events(
+        database,
+        &Client::new(),
+        config,
+        backfill,
+        &snapshots,
+        shutdown,
+    )
+    .await?;
+    if inserted > 0 {
+        cache::invalidate(database).await?;
+    }
+    Ok(inserted)
+}
+
+/// Begins a GET request to the GitHub API, asking for the response in the
+/// `accept` media type. The request is authenticated if a token is
+/// configured.
+pub fn github_request(
+    client: &Client,
+    github: &GitHubSection,
+    url: &str,
+    accept: &str,
+) -> RequestBuilder {
+    let request = client
+        .get(url)
+        .header(ACCEPT, accept)
+        .header(USER_AGENT, "khonsulabs-projects-daemon");
+    match &github.token {
+        Some(token) => request.basic_auth(&github.user, Some(token)),
+        None => request,
     }
 }
 
-async fn fetch_new_events(database: &AsyncDatabase, client: &Client) -> anyhow::Result<()> {
+/// Fetches and inserts the events that aren't stored yet, returning the
+/// number inserted. If shutdown is requested, no more pages are requested and
+/// insertion stops before the next event. Each event is stored in the same
+/// transaction as its search entries and summary, so an insert that is aborted
+/// leaves behind either all of them or none, and the event is fetched again
+/// next time if it wasn't stored.
+async fn fetch_events<C: AsyncConnection + AsyncPubSub>(
+    database: &C,
+    client: &Client,
+    config: &Config,
+    backfill: bool,
+    snapshots: &SnapshotLock,
+    shutdown: &Shutdown,
+) -> anyhow::Result<usize> {
     let mut events_to_process = Vec::new();
+    let mut seen = HashSet::new();
 
     // Loop and gather all the vents we need to insert, potentially across multiple pages.
     'page: for page in 1_u32.. {
+        if shutdown.is_requested() {
+            return Ok(0);
+        }
         tracing::info!("Requesting page {} from github", page);
-        let response = client
-            .get(format!(
-                "https://api.github.com/orgs/khonsulabs/events?page={}&perpage=100",
-                page
-            ))
-            .header(ACCEPT, "application/vnd.github.v3+json")
-            .header(USER_AGENT, "khonsulabs-projects-daemon")
-            .basic_auth("ecton", Some(std::env::var("GITHUB_TOKEN").unwrap()))
-            .send()
-            .await?;
+        let response = github_request(
+            client,
+            &config.github,
+            &format!(
+                "https://api.github.com/orgs/{}/events?page={}&perpage=100",
+                config.github.organization, page
+            ),
+            "application/vnd.github.v3+json",
+        )
+        .send()
+        .await?;
         let text = response.text().await?;
         let events: Vec<Event> = match serde_json::from_str(&text) {
             Ok(events) => events,
@@ -53,26 +156,125 @@ async fn fetch_new_events(database: &AsyncDatabase, client: &Client) -> anyhow::
                     | "SponshorshipEvent"
             )
         }) {
-            if database
-                .view::<GitHubEventById>()
-                .with_key(event.id.clone())
-                .query()
-                .await
-                .unwrap()
-                .is_empty()
-            {
+            if !seen.insert(event.id.clone()) {
+                // Events can shift onto the next page while paging.
+                continue;
+            }
+            if !is_stored(database, &event.id).await? {
                 events_to_process.push(event);
-            } else {
+            } else if !backfill {
                 break 'page;
             }
         }
     }
 
     tracing::info!("Received {} events", events_to_process.len());
+    // GitHub returns the newest events first, but the summaries list each
+    // day's activity in the order it happened.
+    events_to_process.reverse();
+    events_to_process.sort_by_key(|event| event.created_at);
+    let received = events_to_process.len();
+    let mut inserted = 0;
     for event in events_to_process {
-        tracing::debug!("Inserting event {:?}", event);
-        event.push_into_async(database).await?;
+        if shutdown.is_requested() {
+            // Events are inserted oldest first, so the rest are fetched again
+            // next time.
+            tracing::info!(
+                "Stopped after inserting {} of {} events",
+                inserted,
+                received
+            );
+            break;
+        }
+        insert_event(database, event, &config.activity, snapshots).await?;
+        inserted += 1;
     }
 
+    Ok(inserted)
+}
+
+async fn is_stored<C: AsyncConnection>(database: &C, id: &str) -> anyhow::Result<bool> {
+    Ok(!database
+        .view::<GitHubEventById>()
+        .with_key(id.to_string())
+        .query()
+        .await?
+        .is_empty())
+}
+
+/// Stores `event` along with its search index entries and its day's summary
+/// in a single transaction, and publishes it to the live activity stream.
+/// Snapshots taken using `snapshots` wait for the transaction to be applied.
+pub async fn insert_event<C: AsyncConnection + AsyncPubSub>(
+    database: &C,
+    event: Event,
+    activity: &ActivitySection,
+    snapshots: &SnapshotLock,
+) -> anyhow::Result<()> {
+    tracing::debug!("Inserting event {:?}", event);
+    let writing = snapshots.writing().await;
+    let mut transaction = Transaction::new();
+    search::index_event(database, &event, &mut transaction).await?;
+    summaries::record_event(database, &event, activity, &mut transaction).await?;
+    transaction.push(Operation::push_serialized::<Event>(&event)?);
+    let results = database.apply_transaction(transaction).await?;
+    drop(writing);
+    if let Some(OperationResult::DocumentUpdated { header, .. }) = results.last() {
+        live::publish(database, header.id.deserialize::<u64>()?).await?;
+    }
     Ok(())
 }
+
+/// Writes every stored event to `output` as JSON, one event per line,
+/// oldest first. Returns the number of events written.
+pub async fn export_events<C: AsyncConnection, W: Write>(
+    database: &C,
+    mut output: W,
+) -> anyhow::Result<usize> {
+    let events = database
+        .view::<GitHubEventByTimestamp>()
+        .query_with_collection_docs()
+        .await?;
+    for mapping in &events {
+        serde_json::to_writer(&mut output, &mapping.document.contents)?;
+        output.write_all(b"\n")?;
+    }
+    output.flush()?;
+    Ok(events.len())
+}
+
+/// Inserts the events in `input`, in the format written by
+/// [`export_events`], skipping any that are already stored. Returns the
+/// number of events inserted.
+pub async fn import_events<C: AsyncConnection + AsyncPubSub, R: BufRead>(
+    database: &C,
+    input: R,
+    activity: &ActivitySection,
+) -> anyhow::Result<usize> {
+    let mut events = Vec::new();
+    for (index, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event = serde_json::from_str::<Event>(&line)
+            .map_err(|err| anyhow::anyhow!("line {}: {}", index + 1, err))?;
+        events.push(event);
+    }
+    events.sort_by_key(|event| event.created_at);
+
+    // No snapshots are taken while this command runs.
+    let snapshots = SnapshotLock::default();
+    let mut seen = HashSet::new();
+    let mut inserted = 0;
+    for event in events {
+        if seen.insert(event.id.clone()) && !is_stored(database, &event.id).await? {
+            insert_event(database, event, activity, &snapshots).await?;
+            inserted += 1;
+        }
+    }
+    if inserted > 0 {
+        cache::invalidate(database).await?;
+    }
+    Ok(inserted)
+}
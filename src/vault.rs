@@ -0,0 +1,155 @@
+use std::path::{Path, PathBuf};
+
+use bonsaidb::core::connection::AsyncConnection;
+use chrono::Utc;
+use structopt::StructOpt;
+
+use crate::{
+    backup::{self, SnapshotManifest},
+    config::VaultSection,
+    database::{self, VaultKeyLocation},
+};
+
+/// Commands for the vault protecting local storage.
+#[derive(StructOpt, Debug)]
+pub enum VaultCommand {
+    /// Moves every document into new storage with a freshly generated vault
+    /// key and master key, re-encrypting them. The new storage uses the
+    /// currently configured vault key storage, so this also encrypts
+    /// documents written before encryption was enabled. The previous storage
+    /// is kept beside the new one. Stop the server before rotating.
+    Rotate {
+        /// The directory the vault key was kept in before `vault.key_directory`
+        /// was changed. Keys kept within the storage directory are in its
+        /// `vault-keys` directory.
+        #[structopt(long)]
+        previous_key_directory: Option<PathBuf>,
+    },
+}
+
+impl VaultCommand {
+    pub async fn execute(self, path: &Path, vault: &VaultSection) -> anyhow::Result<()> {
+        match self {
+            VaultCommand::Rotate {
+                previous_key_directory,
+            } => {
+                let vault_key = VaultKeyLocation::from_config(vault)?;
+                let previous_key = match previous_key_directory {
+                    Some(path) => VaultKeyLocation::Directory { path },
+                    None => VaultKeyLocation::from_config(vault)?,
+                };
+                let previous = rotate_keys(path, &previous_key, &vault_key).await?;
+                println!("Re-encrypted {} with new keys", path.display());
+                if let Some(previous) = previous {
+                    println!(
+                        "The previous storage was moved to {}. Delete it, along with its vault key, once the new storage has been checked.",
+                        previous.display()
+                    );
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Replaces the storage at `path` with a copy that has a new vault. BonsaiDb
+/// only encrypts with the vault's current master key, so the documents are
+/// backed up and restored into new storage rather than rotated in place.
+/// The existing storage is opened using `previous_key`, and the new storage
+/// keeps its key in `vault_key`. The decrypted backup is written beside
+/// `path`, in a directory only the current user can access, and removed
+/// afterwards. Returns where the previous storage was moved to.
+pub async fn rotate_keys(
+    path: &Path,
+    previous_key: &VaultKeyLocation,
+    vault_key: &VaultKeyLocation,
+) -> anyhow::Result<Option<PathBuf>> {
+    let created_at = Utc::now();
+    let label = format!("rotation-{}", created_at.format("%Y%m%dT%H%M%S%.3fZ"));
+    let directory = backup::staging_directory(path, &label)?;
+
+    let result = async {
+        let database = database::open_local(path, previous_key).await?;
+        database.storage().backup(directory.clone()).await?;
+        let manifest = SnapshotManifest::read(&directory, created_at)?;
+        backup::replace_storage(path, &directory, &manifest, &label, vault_key).await
+    }
+    .await;
+    // The backup contains the decrypted documents.
+    let _ = std::fs::remove_dir_all(&directory);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use bonsaidb::{
+        core::connection::AsyncStorageConnection,
+        local::{
+            config::{Builder, StorageConfiguration},
+            AsyncStorage,
+        },
+    };
+
+    use super::*;
+    use crate::{
+        database::DATABASE_NAME,
+        projects::{self, CATALOG_DIRECTORY},
+        schema::Projects,
+        testing::TestDirectory,
+    };
+
+    /// Returns true if any file beneath `directory` contains `needle`.
+    fn contains(directory: &Path, needle: &[u8]) -> anyhow::Result<bool> {
+        for entry in std::fs::read_dir(directory)? {
+            let path = entry?.path();
+            let found = if path.is_dir() {
+                contains(&path, needle)?
+            } else {
+                std::fs::read(&path)?
+                    .windows(needle.len())
+                    .any(|window| window == needle)
+            };
+            if found {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    #[tokio::test]
+    async fn rotation_encrypts_existing_documents() -> anyhow::Result<()> {
+        let directory = TestDirectory::new("vault-test");
+        let path = directory.join("projects.bonsaidb");
+
+        // Storage written before encryption was enabled.
+        let storage =
+            AsyncStorage::open(StorageConfiguration::new(&path).with_schema::<Projects>()?).await?;
+        storage
+            .create_database::<Projects>(DATABASE_NAME, true)
+            .await?;
+        let database = storage.database::<Projects>(DATABASE_NAME).await?;
+        let count = projects::reload_catalog(&database, Path::new(CATALOG_DIRECTORY)).await?;
+        let repository = projects::catalog(&database).await?["bonsaidb"]
+            .repository
+            .clone();
+        drop((database, storage));
+        assert!(contains(&path, repository.as_bytes())?);
+
+        let previous = rotate_keys(
+            &path,
+            &VaultKeyLocation::Storage,
+            &VaultKeyLocation::Storage,
+        )
+        .await?;
+        assert!(
+            previous.is_some_and(|previous| contains(&previous, repository.as_bytes()).unwrap())
+        );
+        assert!(!contains(&path, repository.as_bytes())?);
+        // Only the storage and its previous copy remain; the decrypted
+        // backup was removed.
+        assert_eq!(std::fs::read_dir(&*directory)?.count(), 2);
+        let rotated = database::open_local(&path, &VaultKeyLocation::Storage).await?;
+        assert_eq!(projects::catalog(&rotated).await?.len(), count);
+        Ok(())
+    }
+}
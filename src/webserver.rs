@@ -1,38 +1,106 @@
-use std::{collections::HashMap, convert::Infallible, net::SocketAddr, sync::Arc};
+use std::{collections::BTreeMap, convert::Infallible, sync::Arc};
 
-use axum::{extract, extract::Extension, response::Html, Router};
-use bonsaidb::{core::connection::AsyncConnection, local::AsyncDatabase};
-use chrono::{Duration, Utc};
-use http::StatusCode;
+use axum::{
+    extract,
+    extract::Extension,
+    response::{Html, Response},
+    Json, Router,
+};
+use bonsaidb::core::{connection::AsyncConnection, pubsub::AsyncPubSub};
+use chrono::{Datelike, Duration, NaiveDate, Utc};
+use http::{HeaderMap, StatusCode, Uri};
 use serde::{Deserialize, Serialize};
 use tera::{Context, Tera};
 use tower_http::services::ServeDir;
 use transmog_json::serde_json;
 
 use crate::{
-    projects::PROJECTS,
-    schema::{GitHubEventByDate, IssuesPayload, PushPayload, Release, ReleasePayload},
+    activity::{self, Heatmap, RepositoryActivity},
+    api,
+    archive::{ArchivePage, Period},
+    cache::{self, CachedPage, PageCache},
+    config::Config,
+    feeds, graph, listener, live,
+    live::LiveUpdates,
+    manifests, projects,
+    schema::{
+        GitHubEventByActor, IssuesPayload, PullRequestPayload, PushPayload, Release, ReleasePayload,
+    },
+    search::{self, SearchResult},
+    shutdown::Shutdown,
+    summaries::{self, ClosedIssue},
 };
 
-const CONTRIBUTOR_EMAILS: [&str; 2] = ["jon@khonsulabs.com", "daxpedda@gmail.com"];
-const FORKED_REPOSITORIES: [&str; 7] = [
-    "iqlusioninc/crates",
-    "novifinancial/opaque-ke",
-    "dalek-cryptography/curve25519-dalek",
-    "RustCrypto/password-hashes",
-    "novifinancial/voprf",
-    "ModProg/derive-where",
-    "ModProg/derive-restricted",
-];
-
-pub async fn serve(database: AsyncDatabase) -> anyhow::Result<()> {
+/// The maximum number of results returned by a search.
+const SEARCH_RESULTS: usize = 50;
+
+/// The number of weeks of history shown in the activity heatmap.
+const HEATMAP_WEEKS: u32 = 53;
+
+/// Serves the site until shutdown is requested, and then until the requests
+/// in progress are finished.
+pub async fn serve<C: AsyncConnection + AsyncPubSub + Clone + 'static>(
+    database: C,
+    config: Arc<Config>,
+    shutdown: Shutdown,
+) -> anyhow::Result<()> {
     let templates = Tera::new("templates/**/*")?;
 
     let templates = Arc::new(templates);
 
+    let live_updates = LiveUpdates::default();
+    let live_database = database.clone();
+    let live_templates = templates.clone();
+    let live_config = config.clone();
+    let broadcast_updates = live_updates.clone();
+    let broadcaster = tokio::spawn(live::broadcast_stored_events(
+        live_database,
+        live_templates,
+        live_config,
+        broadcast_updates,
+    ));
+
+    let cache = PageCache::new(config.server.page_cache_entries);
+    let invalidation_database = database.clone();
+    let invalidated_cache = cache.clone();
+    let invalidation = tokio::spawn(cache::clear_when_invalidated(
+        invalidation_database,
+        invalidated_cache,
+    ));
+
     // build our application with a route
     let app = Router::new()
-        .route("/", axum::routing::get(index_handler))
+        .route("/", axum::routing::get(index_handler::<C>))
+        .route(
+            "/activity/:year/:month",
+            axum::routing::get(month_handler::<C>),
+        )
+        // The router requires both routes to use the same parameter name,
+        // even though this segment is a week such as `2022-W10`.
+        .route("/activity/:year", axum::routing::get(week_handler::<C>))
+        .route(
+            "/contributors/:login",
+            axum::routing::get(contributor_handler::<C>),
+        )
+        .route("/graph", axum::routing::get(graph_handler::<C>))
+        .route("/projects/:name", axum::routing::get(project_handler::<C>))
+        .route(
+            "/events/stream",
+            axum::routing::get(live::stream_handler::<C>),
+        )
+        .route("/search", axum::routing::get(search_handler::<C>))
+        .route("/search.json", axum::routing::get(search_json_handler::<C>))
+        .route("/feed.atom", axum::routing::get(feeds::atom_handler::<C>))
+        .route("/feed.json", axum::routing::get(feeds::json_handler::<C>))
+        .route(
+            "/projects/:name/feed.atom",
+            axum::routing::get(feeds::project_atom_handler::<C>),
+        )
+        .route(
+            "/projects/:name/feed.json",
+            axum::routing::get(feeds::project_json_handler::<C>),
+        )
+        .nest("/api/v1", api::router::<C>())
         .fallback(
             axum::routing::get_service(ServeDir::new("./static")).handle_error(
                 |error: std::io::Error| async move {
@@ -44,29 +112,100 @@ pub async fn serve(database: AsyncDatabase) -> anyhow::Result<()> {
             ),
         )
         .layer(Extension(templates))
+        .layer(Extension(cache))
+        .layer(Extension(live_updates))
+        .layer(Extension(shutdown.clone()))
+        .layer(Extension(config.clone()))
         .layer(Extension(database));
 
     // run it
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
-    println!("listening on {}", addr);
-    axum::Server::bind(&addr)
+    let incoming = listener::listen(&config.server).await?;
+    println!("listening on {}", incoming.address());
+    axum::Server::builder(incoming)
         .serve(app.into_make_service())
+        .with_graceful_shutdown(shutdown.requested())
         .await?;
+
+    // Stop the subscribers too, so that they no longer hold the database.
+    broadcaster.abort();
+    invalidation.abort();
+    let _ = broadcaster.await;
+    let _ = invalidation.await;
+    tracing::info!("Stopped the webserver");
     Ok(())
 }
 
-async fn index_handler(
+async fn index_handler<C: AsyncConnection + Clone + 'static>(
     templates: extract::Extension<Arc<Tera>>,
-    database: extract::Extension<AsyncDatabase>,
-) -> Result<Html<String>, (StatusCode, String)> {
-    index(templates, database)
+    database: extract::Extension<C>,
+    cache: extract::Extension<PageCache>,
+    extract::Query(range): extract::Query<DateRangeQuery>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let today = Utc::today().naive_utc();
+    let period = match (range.from, range.to) {
+        (None, None) => Period::recent(today),
+        _ => {
+            let (from, to) = range.resolve(today, |to| to)?;
+            Period::Days { from, to }
+        }
+    };
+    cache
+        .respond(&uri, &headers, async {
+            html_page(activity_page(templates, database, period).await)
+        })
+        .await
+}
+
+async fn month_handler<C: AsyncConnection + Clone + 'static>(
+    templates: extract::Extension<Arc<Tera>>,
+    database: extract::Extension<C>,
+    cache: extract::Extension<PageCache>,
+    extract::Path((year, month)): extract::Path<(i32, u32)>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let period = Period::month(year, month).ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            format!("{}-{} is not a valid month", year, month),
+        )
+    })?;
+    cache
+        .respond(&uri, &headers, async {
+            html_page(activity_page(templates, database, period).await)
+        })
         .await
-        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))
 }
 
-async fn index(
+async fn week_handler<C: AsyncConnection + Clone + 'static>(
     templates: extract::Extension<Arc<Tera>>,
-    database: extract::Extension<AsyncDatabase>,
+    database: extract::Extension<C>,
+    cache: extract::Extension<PageCache>,
+    extract::Path(week): extract::Path<String>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    let period = Period::parse_week(&week).ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            format!("{:?} is not a valid week, such as 2022-W10", week),
+        )
+    })?;
+    cache
+        .respond(&uri, &headers, async {
+            html_page(activity_page(templates, database, period).await)
+        })
+        .await
+}
+
+/// Renders the activity of each day in `period`, which is limited to
+/// [`MAX_PAGE_DAYS`](crate::archive::MAX_PAGE_DAYS).
+async fn activity_page<C: AsyncConnection + Clone + 'static>(
+    templates: extract::Extension<Arc<Tera>>,
+    database: extract::Extension<C>,
+    period: Period,
 ) -> Result<Html<String>, anyhow::Error> {
     // While debugging, reload the templates always.
     #[cfg(debug_assertions)]
@@ -75,53 +214,174 @@ async fn index(
         Tera::new("templates/**/*")?
     };
 
-    let now = Utc::now();
-    let tomorrow = now + Duration::days(1);
-    let one_month_ago = now - Duration::weeks(4);
-    let events = database
-        .view::<GitHubEventByDate>()
-        .with_key_range(
-            one_month_ago.format("%Y-%m-%d").to_string()..tomorrow.format("%Y-%m-%d").to_string(),
+    let today = Utc::today().naive_utc();
+    let page = ArchivePage::new(period, today, summaries::oldest_day(&*database).await?);
+    let mut days = summaries::summaries(&*database, page.first_day, page.last_day).await?;
+    for day in &mut days {
+        day.repositories.retain(|_key, value| {
+            !value.issues_closed.is_empty() || !value.commit_authors.is_empty()
+        });
+    }
+    days.retain(|d| !d.repositories.is_empty());
+
+    let totals = activity::daily_totals(
+        &*database,
+        None,
+        today - Duration::weeks(HEATMAP_WEEKS.into()),
+        today,
+    )
+    .await?;
+    let heatmap = Heatmap::new(&totals, today, HEATMAP_WEEKS);
+
+    let mut context = Context::new();
+    context.insert("days", &days);
+    context.insert("page", &page);
+    context.insert("latest_event_id", &live::latest_event_id(&*database).await?);
+    context.insert("this_week", &Period::week_containing(today).path());
+    context.insert("this_month", &Period::month_containing(today).path());
+    context.insert("heatmap", &heatmap);
+    let catalog = projects::catalog(&*database).await?;
+    context.insert(
+        "lifecycles",
+        &projects::lifecycles(&*database, &catalog).await?,
+    );
+    context.insert("projects", &catalog);
+    Ok(Html(templates.render("index.html", &context)?))
+}
+
+async fn project_handler<C: AsyncConnection + Clone + 'static>(
+    templates: extract::Extension<Arc<Tera>>,
+    database: extract::Extension<C>,
+    cache: extract::Extension<PageCache>,
+    extract::Path(name): extract::Path<String>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    cache
+        .respond(&uri, &headers, async {
+            match project_page(templates, database, &name).await {
+                Ok(Some(Html(page))) => Ok(CachedPage::html(page)),
+                Ok(None) => Err((
+                    StatusCode::NOT_FOUND,
+                    format!("no project named {:?}", name),
+                )),
+                Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+            }
+        })
+        .await
+}
+
+async fn project_page<C: AsyncConnection + Clone + 'static>(
+    templates: extract::Extension<Arc<Tera>>,
+    database: extract::Extension<C>,
+    name: &str,
+) -> Result<Option<Html<String>>, anyhow::Error> {
+    // While debugging, reload the templates always.
+    #[cfg(debug_assertions)]
+    let templates = {
+        drop(templates);
+        Tera::new("templates/**/*")?
+    };
+
+    let key = name.to_ascii_lowercase();
+    let catalog = projects::catalog(&*database).await?;
+    let project = match catalog.get(&key) {
+        Some(project) => project,
+        None => return Ok(None),
+    };
+    let lifecycle = projects::lifecycles(
+        &*database,
+        &BTreeMap::from([(key.clone(), project.clone())]),
+    )
+    .await?
+    .remove(&key);
+
+    let mut context = Context::new();
+    if let Some(repository) = project.repository_name() {
+        let today = Utc::today().naive_utc();
+        let totals = activity::daily_totals(
+            &*database,
+            Some(&repository),
+            NaiveDate::from_ymd(1970, 1, 1),
+            today,
         )
-        .query_with_collection_docs()
         .await?;
-    let mut days = Vec::new();
-    let mut current_day = None;
-    for event in &events {
-        let github_event = &event.document.contents;
-
-        // Ignore all events from bot actors
-        if github_event.actor.login.ends_with("[bot]") {
-            continue;
-        }
+        context.insert("heatmap", &Heatmap::new(&totals, today, HEATMAP_WEEKS));
+        context.insert("months", &activity::monthly_totals(&totals, today));
+        context.insert(
+            "activity",
+            &activity::repository_activity(&*database, &repository).await?,
+        );
+    } else {
+        context.insert("activity", &RepositoryActivity::default());
+    }
+    context.insert("key", &key);
+    context.insert("project", project);
+    context.insert("lifecycle", &lifecycle);
+    context.insert(
+        "crates",
+        &manifests::crate_graph(&*database)
+            .await?
+            .remove(&key)
+            .unwrap_or_default(),
+    );
+    context.insert("projects", &catalog);
+    Ok(Some(Html(templates.render("project.html", &context)?)))
+}
 
-        let local_repository_name = github_event.repository.name.split('/').nth(1).unwrap();
+async fn contributor_handler<C: AsyncConnection + Clone + 'static>(
+    templates: extract::Extension<Arc<Tera>>,
+    database: extract::Extension<C>,
+    cache: extract::Extension<PageCache>,
+    login: extract::Path<String>,
+    extract::Query(range): extract::Query<DateRangeQuery>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    // Default to the month that `to` falls in.
+    let (from, to) = range.resolve(Utc::today().naive_utc(), |to| to.with_day(1).unwrap())?;
+    cache
+        .respond(&uri, &headers, async {
+            html_page(contributor(templates, database, login, from, to).await)
+        })
+        .await
+}
 
-        let forked_repo = FORKED_REPOSITORIES
-            .into_iter()
-            .find(|repo| repo.split('/').nth(1).unwrap() == local_repository_name);
+async fn contributor<C: AsyncConnection + Clone + 'static>(
+    templates: extract::Extension<Arc<Tera>>,
+    database: extract::Extension<C>,
+    extract::Path(login): extract::Path<String>,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<Html<String>, anyhow::Error> {
+    // While debugging, reload the templates always.
+    #[cfg(debug_assertions)]
+    let templates = {
+        drop(templates);
+        Tera::new("templates/**/*")?
+    };
 
-        if current_day.as_ref() != Some(&event.key) {
-            current_day = Some(event.key);
-            days.push(DayEvents {
-                display: github_event.created_at.format("%A, %B %e, %Y").to_string(),
-                repositories: HashMap::new(),
-                iso_date: event.key.clone(),
-            });
-        }
+    let start = api::start_of_day(from);
+    let end = api::end_of_day(to);
+    let events = database
+        .view::<GitHubEventByActor>()
+        .with_key_range((login.clone(), start)..(login.clone(), end))
+        .query_with_collection_docs()
+        .await?;
 
-        let day_events = days.last_mut().unwrap();
-        let repository = day_events
+    let mut activity = ContributorActivity {
+        login,
+        from: from.format("%Y-%m-%d").to_string(),
+        to: to.format("%Y-%m-%d").to_string(),
+        totals: ContributionTotals::default(),
+        repositories: BTreeMap::new(),
+    };
+    for event in &events {
+        let github_event = &event.document.contents;
+        let repository = activity
             .repositories
-            .entry(local_repository_name.to_string())
-            .or_insert_with(|| ActiveRepository {
-                url: format!(
-                    "https://github.com/{}",
-                    forked_repo.unwrap_or(&github_event.repository.name)
-                ),
-                forked_from: forked_repo.map(|r| r.to_string()),
-                ..ActiveRepository::default()
-            });
+            .entry(github_event.repository.name.clone())
+            .or_default();
 
         match github_event.kind.as_str() {
             "IssuesEvent" => {
@@ -131,30 +391,38 @@ async fn index(
                     continue;
                 }
 
+                activity.totals.issues_closed += 1;
                 repository.issues_closed.push(ClosedIssue {
                     id: payload.issue.number,
                     author: github_event.actor.login.clone(),
-                    url: payload.issue.html_url.to_string(),
-                    title: payload.issue.title.clone(),
+                    url: payload.issue.html_url,
+                    title: payload.issue.title,
+                });
+            }
+            "PullRequestEvent" => {
+                let payload = serde_json::value::from_value::<PullRequestPayload>(
+                    github_event.payload.clone(),
+                )?;
+                if payload.action != "closed" || !payload.pull_request.merged {
+                    continue;
+                }
+
+                activity.totals.pull_requests_merged += 1;
+                repository.pull_requests_merged.push(MergedPullRequest {
+                    id: payload.number,
+                    url: payload.pull_request.html_url,
+                    title: payload.pull_request.title,
                 });
             }
             "PushEvent" => {
                 let push =
                     serde_json::value::from_value::<PushPayload>(github_event.payload.clone())?;
-                for commit in &push.commits {
-                    if forked_repo.is_none()
-                        || CONTRIBUTOR_EMAILS.contains(&commit.author.email.as_str())
-                    {
-                        let repository = repository
-                            .commit_authors
-                            .entry(github_event.actor.login.clone())
-                            .or_default();
-                        repository
-                            .entry(push.reference.split('/').last().unwrap().to_string())
-                            .and_modify(|count| *count += 1)
-                            .or_insert(1);
-                    }
-                }
+                activity.totals.commits += push.commits.len();
+                repository
+                    .commits_by_branch
+                    .entry(push.reference.split('/').next_back().unwrap().to_string())
+                    .and_modify(|count| *count += push.commits.len())
+                    .or_insert(push.commits.len());
             }
             "ReleaseEvent" => {
                 let event =
@@ -163,6 +431,7 @@ async fn index(
                     continue;
                 }
 
+                activity.totals.releases += 1;
                 repository.releases.push(event.release);
             }
 
@@ -170,40 +439,166 @@ async fn index(
         }
     }
 
-    days.reverse();
-    for day in &mut days {
-        day.repositories.retain(|_key, value| {
-            !value.issues_closed.is_empty() || !value.commit_authors.is_empty()
-        });
-    }
-    days.retain(|d| !d.repositories.is_empty());
+    activity.repositories.retain(|_key, value| {
+        !value.commits_by_branch.is_empty()
+            || !value.issues_closed.is_empty()
+            || !value.pull_requests_merged.is_empty()
+            || !value.releases.is_empty()
+    });
 
     let mut context = Context::new();
-    context.insert("days", &days);
-    context.insert("projects", &*PROJECTS);
-    Ok(Html(templates.render("index.html", &context)?))
+    context.insert("activity", &activity);
+    Ok(Html(templates.render("contributor.html", &context)?))
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct DayEvents {
-    pub display: String,
-    pub iso_date: String,
-    pub repositories: HashMap<String, ActiveRepository>,
+async fn graph_handler<C: AsyncConnection + Clone + 'static>(
+    templates: extract::Extension<Arc<Tera>>,
+    database: extract::Extension<C>,
+    cache: extract::Extension<PageCache>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    cache
+        .respond(&uri, &headers, async {
+            html_page(graph_page(templates, database).await)
+        })
+        .await
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
-pub struct ActiveRepository {
-    pub url: String,
-    pub forked_from: Option<String>,
-    pub commit_authors: HashMap<String, HashMap<String, usize>>,
-    pub issues_closed: Vec<ClosedIssue>,
-    pub releases: Vec<Release>,
+async fn graph_page<C: AsyncConnection + Clone + 'static>(
+    templates: extract::Extension<Arc<Tera>>,
+    database: extract::Extension<C>,
+) -> Result<Html<String>, anyhow::Error> {
+    // While debugging, reload the templates always.
+    #[cfg(debug_assertions)]
+    let templates = {
+        drop(templates);
+        Tera::new("templates/**/*")?
+    };
+
+    let catalog = projects::catalog(&*database).await?;
+    let svg = graph::render_svg(&catalog, |key, _project| format!("/projects/{}", key));
+
+    let mut context = Context::new();
+    context.insert("svg", &svg);
+    Ok(Html(templates.render("graph.html", &context)?))
+}
+
+async fn search_handler<C: AsyncConnection + Clone + 'static>(
+    templates: extract::Extension<Arc<Tera>>,
+    database: extract::Extension<C>,
+    cache: extract::Extension<PageCache>,
+    query: extract::Query<SearchQuery>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    cache
+        .respond(&uri, &headers, async {
+            html_page(search_page(templates, database, query).await)
+        })
+        .await
+}
+
+/// Prepares a rendered page to be stored in the [`PageCache`].
+fn html_page(rendered: anyhow::Result<Html<String>>) -> Result<CachedPage, (StatusCode, String)> {
+    rendered
+        .map(|Html(page)| CachedPage::html(page))
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))
+}
+
+async fn search_page<C: AsyncConnection + Clone + 'static>(
+    templates: extract::Extension<Arc<Tera>>,
+    database: extract::Extension<C>,
+    extract::Query(query): extract::Query<SearchQuery>,
+) -> Result<Html<String>, anyhow::Error> {
+    // While debugging, reload the templates always.
+    #[cfg(debug_assertions)]
+    let templates = {
+        drop(templates);
+        Tera::new("templates/**/*")?
+    };
+
+    let results = search::search(&*database, &query.q, SEARCH_RESULTS).await?;
+
+    let mut context = Context::new();
+    context.insert("projects", &projects::catalog(&*database).await?);
+    context.insert("query", &query.q);
+    context.insert("results", &results);
+    Ok(Html(templates.render("search.html", &context)?))
+}
+
+async fn search_json_handler<C: AsyncConnection + Clone + 'static>(
+    database: extract::Extension<C>,
+    extract::Query(query): extract::Query<SearchQuery>,
+) -> Result<Json<Vec<SearchResult>>, (StatusCode, String)> {
+    search::search(&*database, &query.q, SEARCH_RESULTS)
+        .await
+        .map(Json)
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SearchQuery {
+    #[serde(default)]
+    pub q: String,
+}
+
+/// An optional, inclusive range of dates passed as `?from=YYYY-MM-DD&to=YYYY-MM-DD`.
+#[derive(Deserialize, Debug)]
+pub struct DateRangeQuery {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+impl DateRangeQuery {
+    /// Fills in the missing ends of the range, defaulting `to` to
+    /// `default_to` and `from` to `default_from(to)`. Rejects ranges that
+    /// end before they start.
+    pub fn resolve(
+        &self,
+        default_to: NaiveDate,
+        default_from: impl FnOnce(NaiveDate) -> NaiveDate,
+    ) -> Result<(NaiveDate, NaiveDate), (StatusCode, String)> {
+        let to = self.to.unwrap_or(default_to);
+        let from = self.from.unwrap_or_else(|| default_from(to));
+        if from > to {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                String::from("from must not be after to"),
+            ));
+        }
+        Ok((from, to))
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-pub struct ClosedIssue {
+pub struct MergedPullRequest {
     pub id: u64,
-    pub author: String,
     pub url: String,
     pub title: String,
 }
+
+#[derive(Serialize, Debug)]
+pub struct ContributorActivity {
+    pub login: String,
+    pub from: String,
+    pub to: String,
+    pub totals: ContributionTotals,
+    pub repositories: BTreeMap<String, RepositoryContributions>,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct ContributionTotals {
+    pub commits: usize,
+    pub issues_closed: usize,
+    pub pull_requests_merged: usize,
+    pub releases: usize,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct RepositoryContributions {
+    pub commits_by_branch: BTreeMap<String, usize>,
+    pub issues_closed: Vec<ClosedIssue>,
+    pub pull_requests_merged: Vec<MergedPullRequest>,
+    pub releases: Vec<Release>,
+}